@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: MIT AND Apache-2.0
 
 use std::collections::HashMap;
+use std::fmt::Write as _;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -12,7 +13,7 @@ use crate::environment::{
     PackageManifest, ProgressGuard,
 };
 use crate::lock::LockFile;
-use crate::{git, toolchain};
+use crate::{git, plan, toolchain};
 
 /// Directory where API files are stored, relative to each package directory.
 const API_DIR: &str = "api";
@@ -59,6 +60,13 @@ impl ApiConfig {
     }
 }
 
+/// Whether the package keeps API snapshots in its `api/` directory, because the API check is
+/// enabled with `snapshot = true` or snapshots were written with `--snapshot` before.
+pub fn snapshot_enabled(package_dir: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let config = ApiConfig::load(package_dir)?;
+    Ok(config.enabled && (config.snapshot || package_dir.join(API_DIR).is_dir()))
+}
+
 /// Feature configurations to test for API generation.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum FeatureConfig {
@@ -228,7 +236,7 @@ fn check_private_deps(
         let mut message =
             format!("Private dependency exposed in public API of {}:\n", package_name);
         for (feature, item) in leaked_items {
-            message.push_str(&format!("  [{}] {}\n", feature, item));
+            let _ = writeln!(message, "  [{}] {}", feature, item);
         }
         return Err(message.into());
     }
@@ -271,7 +279,7 @@ pub fn run(
         let current_apis = get_package_apis(sh, &package.name, &package.dir)?;
         check_private_deps(&package.name, &current_apis, &api_config.private)?;
 
-        if snapshot || api_config.snapshot {
+        if (snapshot || api_config.snapshot) && !plan::is_active() {
             write_api_files(&package, &current_apis)?;
         }
         if let Some(baseline) = baseline {
@@ -337,6 +345,10 @@ fn get_package_apis(
 
         // Change back to workspace root and parse JSON.
         sh.change_dir(&workspace_root);
+        if plan::is_active() {
+            // No JSON was generated, the plan leaves the APIs empty.
+            continue;
+        }
         let json_path = get_target_dir(sh)?
             .join("doc")
             // Rustdoc replaces hyphens with underscores in the filename.
@@ -357,7 +369,7 @@ fn write_api_files(
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Check that the package's manifest excludes the `api/` directory from publishing.
     let manifest = Manifest::read(&package.dir)?;
-    if !manifest.exclude.iter().any(|e| e.starts_with("api")) {
+    if !manifest.excludes_api_dir() {
         return Err(format!(
             "Package '{}' has an api/ directory but does not exclude it from publishing. \
              Add \"api\" to the `exclude` list in {}/Cargo.toml.",
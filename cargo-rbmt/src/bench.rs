@@ -0,0 +1,325 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Benchmarks compared against a baseline ref.
+//!
+//! The benches of every package are run on the pinned nightly toolchain at HEAD and at the
+//! baseline, and the time per iteration of each benchmark is parsed from the output of either
+//! the libtest harness or criterion.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use xshell::Shell;
+
+use crate::environment::{
+    cargo_cmd, get_workspace_packages, CmdExt, Package, PackageManifest, ProgressGuard,
+};
+use crate::git::GitSwitchGuard;
+use crate::lock::LockFile;
+use crate::toolchain::{prepare_toolchain, Toolchain};
+
+/// Slowdown in percent tolerated when a package does not configure a threshold.
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+/// Nanoseconds per iteration of each benchmark, by package and benchmark name.
+type Results = BTreeMap<String, BTreeMap<String, f64>>;
+
+/// Bench-specific configuration, read from `[package.metadata.rbmt.bench]` in `Cargo.toml`.
+#[derive(Debug, serde::Deserialize)]
+#[serde(default)]
+struct BenchConfig {
+    /// Slowdown of a benchmark in percent which fails the comparison.
+    threshold: f64,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self { Self { threshold: DEFAULT_THRESHOLD } }
+}
+
+impl BenchConfig {
+    /// Load bench configuration from `[package.metadata.rbmt.bench]` in the package's `Cargo.toml`.
+    fn load(package_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(serde::Deserialize, Default)]
+        struct RbmtTable {
+            #[serde(default)]
+            bench: BenchConfig,
+        }
+
+        let contents = std::fs::read_to_string(package_dir.join("Cargo.toml"))?;
+        Ok(toml::from_str::<PackageManifest<RbmtTable>>(&contents)?.package.metadata.rbmt.bench)
+    }
+}
+
+/// A benchmark measured at HEAD, the baseline, or both.
+#[derive(Debug, PartialEq)]
+struct Comparison<'a> {
+    package: &'a str,
+    bench: &'a str,
+    baseline: Option<f64>,
+    current: Option<f64>,
+}
+
+impl Comparison<'_> {
+    /// Change of the time per iteration in percent, positive when it slowed down.
+    fn change(&self) -> Option<f64> {
+        match (self.baseline, self.current) {
+            (Some(baseline), Some(current)) if baseline > 0.0 =>
+                Some((current - baseline) / baseline * 100.0),
+            _ => None,
+        }
+    }
+
+    /// The time at HEAD for printing.
+    fn current_time(&self) -> String { self.current.map(format_time).unwrap_or_default() }
+}
+
+/// Run the benches of the workspace packages and compare them against a baseline ref.
+///
+/// Without a `baseline` the results at HEAD are only printed. With one, a benchmark which
+/// slowed down by more than the threshold of its package fails the run.
+///
+/// # Arguments
+///
+/// * `sh` - The shell context.
+/// * `lockfile` - The lockfile for dependency versions.
+/// * `packages` - Packages to benchmark, empty for all.
+/// * `baseline` - Optional git ref to compare against.
+/// * `threshold` - Slowdown in percent overriding the configured thresholds.
+/// * `cargo_args` - Arguments passed on to `cargo bench`.
+pub fn run(
+    sh: &Shell,
+    lockfile: LockFile,
+    packages: &[String],
+    baseline: Option<&str>,
+    threshold: Option<f64>,
+    cargo_args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut progress = ProgressGuard::new();
+    // Both refs are measured with the nightly pinned at HEAD, so only the code changes.
+    prepare_toolchain(sh, Toolchain::Nightly)?;
+    let packages = get_workspace_packages(sh, packages)?;
+    let mut thresholds = BTreeMap::new();
+    for package in &packages {
+        let threshold = match threshold {
+            Some(threshold) => threshold,
+            None => BenchConfig::load(&package.dir)?.threshold,
+        };
+        thresholds.insert(package.name.as_str(), threshold);
+    }
+
+    let current = {
+        let _lockfile_guard = lockfile.activate(sh)?;
+        measure(sh, &packages, cargo_args)?
+    };
+
+    let Some(baseline) = baseline else {
+        progress.disable();
+        for comparison in compare(&Results::new(), &current) {
+            println!("{} {}: {}", comparison.package, comparison.bench, comparison.current_time());
+        }
+        return Ok(());
+    };
+
+    let baseline_results = {
+        let _git_guard = GitSwitchGuard::new(sh, baseline)?;
+        let _lockfile_guard = lockfile.activate(sh)?;
+        // Packages added since the baseline have nothing to compare against.
+        let existing: Vec<Package> =
+            packages.iter().filter(|p| p.dir.join("Cargo.toml").exists()).cloned().collect();
+        measure(sh, &existing, cargo_args)?
+    };
+
+    progress.disable();
+    println!("Benchmarks compared to {}:", baseline);
+    let mut regressions = Vec::new();
+    for comparison in compare(&baseline_results, &current) {
+        let line = format!("{} {}", comparison.package, comparison.bench);
+        match (comparison.baseline, comparison.current, comparison.change()) {
+            (Some(before), Some(after), Some(change)) => {
+                println!(
+                    "  {}: {} -> {} ({:+.2}%)",
+                    line,
+                    format_time(before),
+                    format_time(after),
+                    change
+                );
+                let threshold = thresholds[comparison.package];
+                if change > threshold {
+                    regressions
+                        .push(format!("{}: {:+.2}% (threshold {}%)", line, change, threshold));
+                }
+            }
+            (None, Some(after), _) => println!("  {}: {} (new)", line, format_time(after)),
+            (Some(before), None, _) => println!("  {}: {} (removed)", line, format_time(before)),
+            _ => println!("  {}: no change measured", line),
+        }
+    }
+
+    if !regressions.is_empty() {
+        return Err(format!(
+            "Benchmarks slowed down by more than the threshold:\n  {}",
+            regressions.join("\n  ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Run the benches of every package at the current commit.
+fn measure(
+    sh: &Shell,
+    packages: &[Package],
+    cargo_args: &[String],
+) -> Result<Results, Box<dyn std::error::Error>> {
+    let mut results = Results::new();
+    for package in packages {
+        let _dir = sh.push_dir(&package.dir);
+        rbmt_eprintln!("Running benches of {}", package.name);
+        let output = cargo_cmd(sh).arg("bench").args(cargo_args).read_with_capture()?;
+        let benches = parse_output(&output);
+        if benches.is_empty() {
+            rbmt_eprintln!("No benchmark results found for {}", package.name);
+        }
+        results.insert(package.name.clone(), benches);
+    }
+    Ok(results)
+}
+
+/// Nanoseconds per iteration of each benchmark in the output of `cargo bench`.
+///
+/// Understands the libtest harness, `test name ... bench: 1,234 ns/iter (+/- 5)`, and
+/// criterion, `name  time: [1.21 µs 1.23 µs 1.25 µs]` where the name goes on a line of its
+/// own if it is long. Criterion's middle estimate is taken.
+fn parse_output(output: &str) -> BTreeMap<String, f64> {
+    let mut benches = BTreeMap::new();
+    // Last line which could be the name of a criterion benchmark.
+    let mut name: Option<&str> = None;
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("test ") {
+            if let Some((bench, result)) = rest.split_once(" ... bench:") {
+                let nanos = result.split_whitespace().next().map(|n| n.replace(',', ""));
+                if let Some(nanos) = nanos.and_then(|n| n.parse().ok()) {
+                    benches.insert(bench.trim().to_owned(), nanos);
+                }
+            }
+            name = None;
+        } else if let Some((prefix, estimates)) = line.split_once("time:") {
+            let bench = match prefix.trim() {
+                "" => name.take(),
+                prefix => Some(prefix),
+            };
+            if let (Some(bench), Some(nanos)) = (bench, parse_estimate(estimates)) {
+                benches.insert(bench.to_owned(), nanos);
+            }
+        } else if !line.is_empty() && !line.starts_with(char::is_whitespace) {
+            name = Some(line.trim());
+        }
+    }
+    benches
+}
+
+/// Nanoseconds of the middle estimate in `[1.21 µs 1.23 µs 1.25 µs]`.
+fn parse_estimate(estimates: &str) -> Option<f64> {
+    let estimates = estimates.trim().strip_prefix('[')?.split(']').next()?;
+    let parts: Vec<&str> = estimates.split_whitespace().collect();
+    let [_, _, value, unit, _, _] = parts.as_slice() else {
+        return None;
+    };
+    let scale = match *unit {
+        "ps" => 1e-3,
+        "ns" => 1.0,
+        "µs" | "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        _ => return None,
+    };
+    value.parse::<f64>().ok().map(|value| value * scale)
+}
+
+/// Pair up the benchmarks measured at the baseline and HEAD, ordered by package and name.
+fn compare<'a>(baseline: &'a Results, current: &'a Results) -> Vec<Comparison<'a>> {
+    let mut comparisons: BTreeMap<(&str, &str), Comparison> = BTreeMap::new();
+    for (results, is_current) in [(baseline, false), (current, true)] {
+        for (package, benches) in results {
+            for (bench, &nanos) in benches {
+                let comparison = comparisons.entry((package, bench)).or_insert(Comparison {
+                    package,
+                    bench,
+                    baseline: None,
+                    current: None,
+                });
+                if is_current {
+                    comparison.current = Some(nanos);
+                } else {
+                    comparison.baseline = Some(nanos);
+                }
+            }
+        }
+    }
+    comparisons.into_values().collect()
+}
+
+/// A time per iteration in the largest unit which keeps it at least 1.
+fn format_time(nanos: f64) -> String {
+    if nanos >= 1e9 {
+        format!("{:.2} s", nanos / 1e9)
+    } else if nanos >= 1e6 {
+        format!("{:.2} ms", nanos / 1e6)
+    } else if nanos >= 1e3 {
+        format!("{:.2} µs", nanos / 1e3)
+    } else {
+        format!("{:.2} ns", nanos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_libtest_and_criterion_output() {
+        let output = "\
+running 3 tests
+test hash::tests::roundtrip ... ignored
+test hash::benches::sha256 ... bench:       1,234 ns/iter (+/- 56)
+test hash::benches::ripemd ... bench:         987.50 ns/iter (+/- 12.25)
+
+test result: ok. 0 passed; 0 failed; 1 ignored; 2 measured; 0 filtered out
+
+sha256/64               time:   [215.32 ns 216.01 ns 216.81 ns]
+                        thrpt:  [281.50 MiB/s 282.54 MiB/s 283.44 MiB/s]
+Found 3 outliers among 100 measurements (3.00%)
+  3 (3.00%) high mild
+sha256/a_rather_long_benchmark_name
+                        time:   [1.2100 µs 1.2300 µs 1.2500 µs]
+                        change: [-1.2% +0.3% +1.9%] (p = 0.71 > 0.05)
+";
+        let benches = parse_output(output);
+        assert_eq!(benches.len(), 4);
+        assert_eq!(benches["hash::benches::sha256"], 1234.0);
+        assert_eq!(benches["hash::benches::ripemd"], 987.5);
+        assert_eq!(benches["sha256/64"], 216.01);
+        assert_eq!(benches["sha256/a_rather_long_benchmark_name"], 1230.0);
+    }
+
+    #[test]
+    fn compare_against_baseline() {
+        let baseline: Results = [(
+            "hashes".to_owned(),
+            [("sha256".to_owned(), 100.0), ("removed".to_owned(), 5.0)].into(),
+        )]
+        .into();
+        let current: Results = [(
+            "hashes".to_owned(),
+            [("sha256".to_owned(), 112.0), ("added".to_owned(), 7.0)].into(),
+        )]
+        .into();
+
+        let comparisons = compare(&baseline, &current);
+        let names: Vec<&str> = comparisons.iter().map(|c| c.bench).collect();
+        assert_eq!(names, ["added", "removed", "sha256"]);
+        assert_eq!(comparisons[0].change(), None);
+        assert_eq!(comparisons[1].current, None);
+        assert!((comparisons[2].change().unwrap() - 12.0).abs() < 1e-9);
+    }
+}
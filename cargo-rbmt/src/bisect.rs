@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Find the first failing commit between a baseline and HEAD by binary search.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use xshell::Shell;
+
+use crate::environment::{
+    cargo_cmd, get_target_dir, get_workspace_packages, CmdExt, CommandError, ProgressGuard,
+};
+use crate::git::{self, GitSwitchGuard};
+use crate::lock::LockFile;
+use crate::toolchain::{prepare_toolchain, Toolchain};
+
+/// The command deciding whether a commit is good or bad.
+enum Predicate {
+    /// A cargo command, e.g. `cargo test --lib`.
+    Cargo(Vec<String>),
+    /// An rbmt subcommand, e.g. `test --toolchain msrv`, run with the current executable.
+    Rbmt {
+        exe: PathBuf,
+        args: Vec<String>,
+        /// Where a `test` subcommand writes its report, to find the failing unit.
+        report: Option<PathBuf>,
+    },
+}
+
+/// Exit code with which a command marks a commit as one it cannot test, as for `git bisect run`.
+const SKIP_EXIT_CODE: i32 = 125;
+
+/// How a commit fared.
+enum Verdict {
+    Good,
+    /// The predicate failed, with the failing unit if it is known.
+    Bad(Option<String>),
+    /// The predicate exited with [`SKIP_EXIT_CODE`], so the commit is neither good nor bad.
+    Skip,
+}
+
+impl Verdict {
+    /// The verdict of a predicate command, looking up the failing unit only if it failed.
+    fn of(
+        result: Result<(), Box<dyn std::error::Error>>,
+        unit: impl FnOnce() -> Option<String>,
+    ) -> Self {
+        match result {
+            Ok(()) => Self::Good,
+            Err(e)
+                if e.downcast_ref::<CommandError>().and_then(|e| e.code)
+                    == Some(SKIP_EXIT_CODE) =>
+                Self::Skip,
+            Err(_) => Self::Bad(unit()),
+        }
+    }
+}
+
+impl Predicate {
+    /// Parse the command after `--`.
+    ///
+    /// A `test` subcommand inherits the `lockfile` and `toolchain` of the bisection unless it
+    /// selects its own, so it does not replace the lockfile activated at each commit with its
+    /// default.
+    fn new(
+        args: &[String],
+        report_path: &Path,
+        lockfile: LockFile,
+        toolchain: Toolchain,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match args.split_first() {
+            None => Err("Expected a command to run at each commit".into()),
+            Some((first, rest)) if first == "cargo" => {
+                if rest.is_empty() {
+                    return Err("Expected a cargo subcommand".into());
+                }
+                Ok(Self::Cargo(rest.to_vec()))
+            }
+            Some((first, rest)) => {
+                let mut args = if first == "rbmt" { rest.to_vec() } else { args.to_vec() };
+                if args.first().is_none_or(|a| a != "test") {
+                    return Ok(Self::Rbmt { exe: std::env::current_exe()?, args, report: None });
+                }
+
+                let mut inherited = Vec::new();
+                for (flags, value) in [
+                    (&["--lockfile", "--lock-file"][..], lockfile.to_possible_value()),
+                    (&["--toolchain"][..], toolchain.to_possible_value()),
+                ] {
+                    if let Some(value) = value.filter(|_| !has_flag(&args, flags)) {
+                        inherited.extend([flags[0].to_owned(), value.get_name().to_owned()]);
+                    }
+                }
+                // Ask for a report which names the failing unit, unless it already writes one.
+                let report = (!has_flag(&args, &["--report"])).then(|| report_path.to_path_buf());
+                if let Some(report) = &report {
+                    let report = report.display().to_string();
+                    inherited.extend(["--report".to_owned(), report]);
+                }
+                args.splice(1..1, inherited);
+                Ok(Self::Rbmt { exe: std::env::current_exe()?, args, report })
+            }
+        }
+    }
+
+    /// Run the predicate at the current commit.
+    fn check(
+        &self,
+        sh: &Shell,
+        toolchain: Toolchain,
+        packages: &[String],
+    ) -> Result<Verdict, Box<dyn std::error::Error>> {
+        match self {
+            Self::Cargo(args) => {
+                prepare_toolchain(sh, toolchain)?;
+                let mut cmd = cargo_cmd(sh).arg(&args[0]);
+                // Add package flags after subcommand, but before other arguments.
+                for package in get_workspace_packages(sh, packages)? {
+                    cmd = cmd.arg("-p").arg(&package.id);
+                }
+                // Fully captured, so the exit code is known in every output mode.
+                Ok(Verdict::of(cmd.args(&args[1..]).run_with_full_capture(), || None))
+            }
+            Self::Rbmt { exe, args, report } => {
+                if let Some(report) = report.as_ref().filter(|r| r.exists()) {
+                    std::fs::remove_file(report)?;
+                }
+                // The pinned rbmt version may differ between commits, this executable decides.
+                let mut cmd = sh.cmd(exe).arg("--ignore-version");
+                for package in packages {
+                    cmd = cmd.arg("-p").arg(package);
+                }
+                Ok(Verdict::of(cmd.args(args).run_with_full_capture(), || {
+                    report.as_deref().and_then(failing_unit)
+                }))
+            }
+        }
+    }
+}
+
+/// Whether the arguments set one of the long `flags`, as `--flag value` or `--flag=value`.
+fn has_flag(args: &[String], flags: &[&str]) -> bool {
+    args.iter().any(|arg| {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        flags.contains(&name)
+    })
+}
+
+/// The first failed unit in a JSON test report, e.g. `feature-set all features in bitcoin`.
+fn failing_unit(report: &Path) -> Option<String> {
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(report).ok()?).ok()?;
+    report["commits"].as_array()?.iter().find_map(|commit| {
+        commit["packages"].as_array()?.iter().find_map(|package| {
+            package["units"].as_array()?.iter().find(|unit| unit["status"] == "failed").map(
+                |unit| {
+                    format!(
+                        "{} {} in {}",
+                        unit["kind"].as_str().unwrap_or("unit"),
+                        unit["name"].as_str().unwrap_or("(unknown)"),
+                        package["name"].as_str().unwrap_or("(unknown)")
+                    )
+                },
+            )
+        })
+    })
+}
+
+/// Binary search the commits between `baseline` and HEAD for the first one where the command
+/// fails.
+///
+/// The baseline is checked first and must pass, otherwise a broken command would mark every
+/// commit as bad. HEAD is checked next, and if it passes there is nothing to find. A commit where
+/// the command exits with [`SKIP_EXIT_CODE`] is skipped and a neighbouring one tested instead. If
+/// only skipped commits are left, every one which could be the first bad commit is reported.
+/// Each commit is checked out with [`GitSwitchGuard`] and the lockfile activated before running
+/// the command.
+///
+/// # Arguments
+///
+/// * `sh` - The shell environment.
+/// * `lockfile` - Which lockfile variant to activate at each commit.
+/// * `toolchain` - Toolchain for a cargo command and the default of an rbmt `test`.
+/// * `baseline` - The last known good ref.
+/// * `packages` - Packages to run the command on (empty = all packages).
+/// * `args` - The command, either `cargo <subcommand> ...` or an rbmt subcommand such as `test`.
+pub fn run(
+    sh: &Shell,
+    lockfile: LockFile,
+    toolchain: Toolchain,
+    baseline: &str,
+    packages: &[String],
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut progress = ProgressGuard::new();
+    let report_path = get_target_dir(sh)?.join("rbmt").join("bisect-report.json");
+    let predicate = Predicate::new(args, &report_path, lockfile, toolchain)?;
+
+    let commits = git::list_commits(sh, baseline)?;
+    if commits.is_empty() {
+        return Err(format!("No commits found between '{}' and HEAD", baseline).into());
+    }
+
+    let check = |sha: &str| -> Result<Verdict, Box<dyn std::error::Error>> {
+        rbmt_eprintln!("Checking commit {}", &sha[..12]);
+        let _git_guard = GitSwitchGuard::new(sh, sha)?;
+        let _lockfile_guard = lockfile.activate(sh)?;
+        let verdict = predicate.check(sh, toolchain, packages)?;
+        match &verdict {
+            Verdict::Good => rbmt_eprintln!("Commit {} is good", &sha[..12]),
+            Verdict::Bad(_) => rbmt_eprintln!("Commit {} is bad", &sha[..12]),
+            Verdict::Skip => rbmt_eprintln!("Commit {} cannot be tested, skipping", &sha[..12]),
+        }
+        Ok(verdict)
+    };
+
+    // The commits are listed from where HEAD branched off the baseline, so check that one.
+    let base = rbmt_cmd!(sh, "git merge-base HEAD {baseline}").read()?;
+    match check(base.trim())? {
+        Verdict::Good => {}
+        Verdict::Bad(_) =>
+            return Err(format!(
+                "The command fails at the baseline '{}', so every commit would be bad. \
+                 Check that the command is correct and passes at the baseline.",
+                baseline
+            )
+            .into()),
+        Verdict::Skip =>
+            return Err(format!("The command cannot test the baseline '{}'", baseline).into()),
+    }
+
+    // Invariant: every commit before `low` is good, and `high` is bad.
+    let mut high = commits.len() - 1;
+    let mut tested = 1;
+    let mut unit = match check(&commits[high])? {
+        Verdict::Good => {
+            progress.disable();
+            println!("HEAD passes, no failing commit since {}", baseline);
+            return Ok(());
+        }
+        Verdict::Bad(unit) => unit,
+        Verdict::Skip => return Err("The command cannot test HEAD".into()),
+    };
+    let mut low = 0;
+    let mut skipped = BTreeSet::new();
+    while let Some(mid) = next_candidate(low, high, &skipped) {
+        tested += 1;
+        match check(&commits[mid])? {
+            Verdict::Good => low = mid + 1,
+            Verdict::Bad(mid_unit) => {
+                high = mid;
+                unit = mid_unit;
+            }
+            Verdict::Skip => {
+                skipped.insert(mid);
+            }
+        }
+    }
+
+    progress.disable();
+    if low < high {
+        // Every commit left before `high` was skipped, so any of them could be the first bad one.
+        println!("The first bad commit could be any of:");
+        for sha in &commits[low..=high] {
+            let subject = rbmt_cmd!(sh, "git log -1 --format=%s {sha}").read()?;
+            println!("  {} {}", sha, subject.trim());
+        }
+    } else {
+        let sha = &commits[high];
+        let subject = rbmt_cmd!(sh, "git log -1 --format=%s {sha}").read()?;
+        println!("First bad commit: {} {}", sha, subject.trim());
+    }
+    if let Some(unit) = unit {
+        println!("Failing unit: {}", unit);
+    }
+    println!("Checked {} of {} commits since {}", tested, commits.len(), baseline);
+    Ok(())
+}
+
+/// The next commit to check between `low` and `high`: the untested one closest to the middle,
+/// or `None` if the range is empty or every commit in it was skipped.
+fn next_candidate(low: usize, high: usize, skipped: &BTreeSet<usize>) -> Option<usize> {
+    let mid = low + high.saturating_sub(low) / 2;
+    (low..high).filter(|i| !skipped.contains(i)).min_by_key(|i| i.abs_diff(mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> { args.iter().map(|&a| a.to_owned()).collect() }
+
+    #[test]
+    fn predicate_requests_test_report() {
+        let report = Path::new("/t/rbmt/bisect-report.json");
+        let new = |predicate: &[&str]| {
+            Predicate::new(&args(predicate), report, LockFile::Minimal, Toolchain::Stable).unwrap()
+        };
+        match new(&["rbmt", "test", "--toolchain", "msrv"]) {
+            Predicate::Rbmt { args: rbmt_args, report: Some(path), .. } => {
+                assert_eq!(
+                    rbmt_args,
+                    args(&[
+                        "test",
+                        "--lockfile",
+                        "minimal",
+                        "--report",
+                        "/t/rbmt/bisect-report.json",
+                        "--toolchain",
+                        "msrv"
+                    ])
+                );
+                assert_eq!(path, report);
+            }
+            _ => panic!("expected an rbmt test predicate with a report"),
+        }
+
+        // An explicit report, lockfile, and toolchain are left alone, as are other subcommands.
+        let explicit = args(&["test", "--report", "out.xml", "--lock-file=existing"]);
+        let explicit = [explicit, args(&["--toolchain", "nightly"])].concat();
+        assert!(matches!(
+            Predicate::new(&explicit, report, LockFile::Minimal, Toolchain::Stable).unwrap(),
+            Predicate::Rbmt { args, report: None, .. } if args == explicit
+        ));
+        assert!(matches!(
+            new(&["lint"]),
+            Predicate::Rbmt { args: rbmt_args, report: None, .. } if rbmt_args == args(&["lint"])
+        ));
+        assert!(matches!(
+            new(&["cargo", "check"]),
+            Predicate::Cargo(cargo_args) if cargo_args == args(&["check"])
+        ));
+        assert!(Predicate::new(&args(&["cargo"]), report, LockFile::Minimal, Toolchain::Stable)
+            .is_err());
+    }
+
+    #[test]
+    fn next_candidate_steps_around_skipped_commits() {
+        let none = BTreeSet::new();
+        assert_eq!(next_candidate(0, 8, &none), Some(4));
+        assert_eq!(next_candidate(3, 4, &none), Some(3));
+        assert_eq!(next_candidate(4, 4, &none), None);
+
+        // The closest untested commit to the middle, preferring the earlier one on a tie.
+        assert_eq!(next_candidate(0, 8, &BTreeSet::from([4])), Some(3));
+        assert_eq!(next_candidate(0, 8, &BTreeSet::from([3, 4])), Some(5));
+        assert_eq!(next_candidate(2, 5, &BTreeSet::from([2, 3, 4])), None);
+    }
+}
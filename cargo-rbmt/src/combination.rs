@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Lockfile and toolchain combinations to run a task with.
+//!
+//! `--lockfile` and `--toolchain` take a comma separated list of values or `all`, e.g.
+//! `--lockfile minimal,maximum --toolchain all`, and the task runs with every combination.
+
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::lock::LockFile;
+use crate::toolchain::Toolchain;
+
+/// Lockfiles selected by `all`, every lockfile rbmt generates.
+pub(crate) const ALL_LOCKFILES: [LockFile; 3] =
+    [LockFile::Minimal, LockFile::Maximum, LockFile::Recent];
+
+/// Toolchains selected by `all`.
+const ALL_TOOLCHAINS: [Toolchain; 3] = [Toolchain::Nightly, Toolchain::Stable, Toolchain::Msrv];
+
+/// A single value of a `--lockfile` or `--toolchain` list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection<T> {
+    /// Every value, see [`ALL_LOCKFILES`] and [`ALL_TOOLCHAINS`].
+    All,
+    One(T),
+}
+
+/// Parse a single value of a list, either `all` or one of the values of `T`.
+pub fn parse<T: ValueEnum>(s: &str) -> Result<Selection<T>, String> {
+    if s.eq_ignore_ascii_case("all") {
+        return Ok(Selection::All);
+    }
+    T::from_str(s, true).map(Selection::One).map_err(|_| {
+        let values: Vec<String> = T::value_variants().iter().map(name).collect();
+        format!("expected one of all, {}", values.join(", "))
+    })
+}
+
+/// The distinct values selected, in the order they were first given.
+fn expand<T: Copy + PartialEq>(selections: &[Selection<T>], all: &[T]) -> Vec<T> {
+    let mut values = Vec::new();
+    for selection in selections {
+        let selected = match selection {
+            Selection::All => all,
+            Selection::One(value) => std::slice::from_ref(value),
+        };
+        for value in selected {
+            if !values.contains(value) {
+                values.push(*value);
+            }
+        }
+    }
+    values
+}
+
+/// The distinct lockfiles selected, for tasks which do not take a toolchain.
+pub fn lockfiles(selections: &[Selection<LockFile>]) -> Vec<LockFile> {
+    expand(selections, &ALL_LOCKFILES)
+}
+
+/// A lockfile and toolchain to run a task with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Combination {
+    pub lockfile: LockFile,
+    pub toolchain: Toolchain,
+}
+
+impl Combination {
+    /// Every combination of the selected lockfiles and toolchains, grouped by lockfile.
+    pub fn all_of(
+        lockfiles: &[Selection<LockFile>],
+        toolchains: &[Selection<Toolchain>],
+    ) -> Vec<Self> {
+        let toolchains = expand(toolchains, &ALL_TOOLCHAINS);
+        expand(lockfiles, &ALL_LOCKFILES)
+            .into_iter()
+            .flat_map(|lockfile| {
+                toolchains.iter().map(move |&toolchain| Self { lockfile, toolchain })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Combination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lockfile {}, toolchain {}", name(&self.lockfile), name(&self.toolchain))
+    }
+}
+
+/// Name of a value as given on the command line.
+fn name<T: ValueEnum>(value: &T) -> String {
+    value.to_possible_value().map(|value| value.get_name().to_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinations_of_lists() {
+        let lockfiles = ["minimal", "maximum"].map(|s| parse::<LockFile>(s).unwrap());
+        let toolchains = ["stable", "msrv", "STABLE"].map(|s| parse::<Toolchain>(s).unwrap());
+        let combinations = Combination::all_of(&lockfiles, &toolchains);
+        let names: Vec<String> = combinations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            names,
+            [
+                "lockfile minimal, toolchain stable",
+                "lockfile minimal, toolchain msrv",
+                "lockfile maximum, toolchain stable",
+                "lockfile maximum, toolchain msrv",
+            ]
+        );
+        assert!(parse::<Toolchain>("beta").unwrap_err().contains("all, nightly, stable, msrv"));
+    }
+
+    #[test]
+    fn all_expands_to_generated_lockfiles() {
+        let all = Combination::all_of(&[Selection::All], &[Selection::One(Toolchain::Stable)]);
+        let lockfiles: Vec<LockFile> = all.iter().map(|c| c.lockfile).collect();
+        assert_eq!(lockfiles, ALL_LOCKFILES);
+
+        let existing = Selection::One(LockFile::Existing);
+        assert_eq!(Combination::all_of(&[existing, Selection::All], &[Selection::All]).len(), 12);
+    }
+}
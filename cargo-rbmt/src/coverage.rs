@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Code coverage of the whole feature matrix.
+//!
+//! Every feature set `test` exercises is run with `-C instrument-coverage`, and the profiles of
+//! all of them are merged into a single report with the tools of the `llvm-tools` component.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use xshell::Shell;
+
+use crate::environment::{
+    cargo_cmd, get_target_dir, get_workspace_packages, CmdExt, ProgressGuard,
+};
+use crate::git::{self, GitSwitchGuard};
+use crate::lock::LockFile;
+use crate::test;
+use crate::toolchain::{add_component, host_target, prepare_toolchain, Toolchain};
+
+/// Dependencies and the standard library are left out of the report.
+const IGNORE_FILENAME_REGEX: &str = r"(\.cargo/(registry|git)/|\.rustup/|/rustc/)";
+
+/// Line coverage totals of a report.
+#[derive(Debug, Clone, Copy)]
+struct Coverage {
+    covered: u64,
+    count: u64,
+    percent: f64,
+}
+
+/// The binaries of the `llvm-tools` component for the active toolchain.
+struct LlvmTools {
+    profdata: PathBuf,
+    cov: PathBuf,
+}
+
+impl LlvmTools {
+    fn find(sh: &Shell) -> Result<Self, Box<dyn std::error::Error>> {
+        let sysroot = rbmt_cmd!(sh, "rustc --print sysroot").read()?;
+        let bin = Path::new(sysroot.trim()).join("lib/rustlib").join(host_target(sh)?).join("bin");
+        let tools = Self {
+            profdata: bin.join(format!("llvm-profdata{}", std::env::consts::EXE_SUFFIX)),
+            cov: bin.join(format!("llvm-cov{}", std::env::consts::EXE_SUFFIX)),
+        };
+        if !tools.profdata.exists() || !tools.cov.exists() {
+            return Err(format!(
+                "llvm-profdata and llvm-cov not found in {}, install the llvm-tools component",
+                bin.display()
+            )
+            .into());
+        }
+        Ok(tools)
+    }
+}
+
+/// Measure the coverage of the feature matrix of the workspace packages.
+///
+/// Writes `lcov.info` and an HTML report to `<target-dir>/rbmt/coverage/`. With a `baseline`,
+/// the coverage at that ref is also measured (into `baseline/`) and the change is reported. Both
+/// refs sample feature subsets with HEAD's seed, so they measure the same matrix.
+///
+/// # Arguments
+///
+/// * `sh` - The shell context.
+/// * `lockfile` - The lockfile for dependency versions.
+/// * `packages` - Packages to measure, empty for all.
+/// * `baseline` - Optional git ref to compare against.
+pub fn run(
+    sh: &Shell,
+    lockfile: LockFile,
+    packages: &[String],
+    baseline: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut progress = ProgressGuard::new();
+    prepare_toolchain(sh, Toolchain::Stable)?;
+    add_component(sh, "llvm-tools")?;
+    let tools = LlvmTools::find(sh)?;
+
+    let out_dir = get_target_dir(sh)?.join("rbmt").join("coverage");
+    // Instrumented builds are kept apart so they do not invalidate the regular ones.
+    let _target_dir = sh.push_env("CARGO_TARGET_DIR", out_dir.join("build"));
+    let rustflags = match std::env::var("RUSTFLAGS") {
+        Ok(flags) if !flags.trim().is_empty() => format!("{} -C instrument-coverage", flags),
+        _ => "-C instrument-coverage".to_owned(),
+    };
+    let _rustflags = sh.push_env("RUSTFLAGS", rustflags);
+
+    // Seed for the log sampling strategy, shared with the baseline.
+    let seed = git::current_commit_id(sh);
+    let coverage = {
+        let _lockfile_guard = lockfile.activate(sh)?;
+        measure(sh, &tools, packages, seed.as_deref(), &out_dir)?
+    };
+
+    let baseline_coverage = match baseline {
+        Some(baseline) => {
+            let _git_guard = GitSwitchGuard::new(sh, baseline)?;
+            let _lockfile_guard = lockfile.activate(sh)?;
+            Some(measure(sh, &tools, packages, seed.as_deref(), &out_dir.join("baseline"))?)
+        }
+        None => None,
+    };
+
+    rbmt_eprintln!("Wrote coverage reports to {}", out_dir.display());
+    progress.disable();
+    println!("Line coverage: {}/{} ({:.2}%)", coverage.covered, coverage.count, coverage.percent);
+    if let (Some(baseline), Some(baseline_coverage)) = (baseline, baseline_coverage) {
+        println!(
+            "Baseline {}: {}/{} ({:.2}%), change {:+.2}%",
+            baseline,
+            baseline_coverage.covered,
+            baseline_coverage.count,
+            baseline_coverage.percent,
+            coverage.percent - baseline_coverage.percent
+        );
+    }
+    Ok(())
+}
+
+/// Run the feature matrix of every package at the current commit, sampled with `seed`, and
+/// write the merged profile, `lcov.info`, and `html/` to `dir`.
+fn measure(
+    sh: &Shell,
+    tools: &LlvmTools,
+    packages: &[String],
+    seed: Option<&str>,
+    dir: &Path,
+) -> Result<Coverage, Box<dyn std::error::Error>> {
+    let profraw_dir = dir.join("profraw");
+    if profraw_dir.exists() {
+        fs::remove_dir_all(&profraw_dir)?;
+    }
+    fs::create_dir_all(&profraw_dir)?;
+    let _profile_file = sh.push_env("LLVM_PROFILE_FILE", profraw_dir.join("%p-%m.profraw"));
+
+    // Test binaries of every feature set, which llvm-cov needs to map the profiles to sources.
+    let mut objects = BTreeSet::new();
+    for package in get_workspace_packages(sh, packages)? {
+        let _dir = sh.push_dir(&package.dir);
+        for features in test::feature_matrix(&package, seed.map(str::to_owned))? {
+            let args = test::feature_args(features.as_deref());
+            rbmt_eprintln!("Measuring coverage of {} with {}", package.name, args.join(" "));
+            let output = cargo_cmd(sh)
+                .arg("test")
+                .arg("--no-run")
+                .arg("--message-format=json")
+                .args(&args)
+                .read()?;
+            objects.extend(test_executables(&output));
+            cargo_cmd(sh).arg("test").args(&args).run_with_capture()?;
+        }
+    }
+
+    let profiles: Vec<String> = fs::read_dir(&profraw_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+        .map(|path| path.display().to_string())
+        .collect();
+    if profiles.is_empty() || objects.is_empty() {
+        return Err("No coverage profiles were written, are there any tests?".into());
+    }
+
+    // Listing the profiles in a file avoids the command line length limit.
+    let profile_list = dir.join("profiles.txt");
+    fs::write(&profile_list, profiles.join("\n"))?;
+    let profdata = dir.join("coverage.profdata");
+    let profdata_bin = &tools.profdata;
+    rbmt_cmd!(sh, "{profdata_bin} merge -sparse --input-files={profile_list} -o {profdata}")
+        .run_with_capture()?;
+
+    let mut cov_args = vec![
+        format!("-instr-profile={}", profdata.display()),
+        format!("-ignore-filename-regex={}", IGNORE_FILENAME_REGEX),
+    ];
+    for object in &objects {
+        cov_args.push("-object".to_owned());
+        cov_args.push(object.clone());
+    }
+
+    let cov = &tools.cov;
+    let lcov = rbmt_cmd!(sh, "{cov} export -format=lcov").args(&cov_args).read()?;
+    fs::write(dir.join("lcov.info"), lcov)?;
+    let html_dir = dir.join("html");
+    rbmt_cmd!(sh, "{cov} show -format=html -output-dir={html_dir}")
+        .args(&cov_args)
+        .run_with_capture()?;
+
+    let summary = rbmt_cmd!(sh, "{cov} export -summary-only").args(&cov_args).read()?;
+    let summary: serde_json::Value = serde_json::from_str(&summary)?;
+    let lines = &summary["data"][0]["totals"]["lines"];
+    match (lines["covered"].as_u64(), lines["count"].as_u64(), lines["percent"].as_f64()) {
+        (Some(covered), Some(count), Some(percent)) => Ok(Coverage { covered, count, percent }),
+        _ => Err("Missing line totals in llvm-cov summary".into()),
+    }
+}
+
+/// Paths of the test executables built, from cargo's JSON messages.
+fn test_executables(messages: &str) -> Vec<String> {
+    messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| {
+            message["reason"] == "compiler-artifact" && message["profile"]["test"] == true
+        })
+        .filter_map(|message| message["executable"].as_str().map(str::to_owned))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_executables_from_messages() {
+        let messages = [
+            r#"{"reason":"compiler-artifact","target":{"name":"a"},"profile":{"test":false},"executable":null}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"a"},"profile":{"test":true},"executable":"/t/debug/deps/a-b3ba"}"#,
+            r#"{"reason":"compiler-artifact","target":{"name":"ex"},"profile":{"test":false},"executable":"/t/debug/examples/ex"}"#,
+            r#"{"reason":"build-finished","success":true}"#,
+            "not json",
+        ]
+        .join("\n");
+        assert_eq!(test_executables(&messages), vec!["/t/debug/deps/a-b3ba".to_owned()]);
+    }
+}
@@ -1,10 +1,14 @@
 // SPDX-License-Identifier: MIT AND Apache-2.0
 
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 use xshell::{Cmd, Shell};
 
+use crate::plan;
+
 /// Environment variable to control output verbosity.
 const LOG_LEVEL_ENV_VAR: &str = "RBMT_LOG_LEVEL";
 
@@ -34,34 +38,111 @@ impl OutputMode {
 pub trait CmdExt {
     /// If running in `progress` or `quiet` mode, capture output and display on failure
     /// for easier diagnoses for the caller.
-    fn run_with_capture(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    fn run_with_capture(self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Like [`CmdExt::run_with_capture`], but captures output in every mode so it is kept in
+    /// the [`CommandError`] on failure. In `verbose` mode the output is shown once the command
+    /// finishes instead of as it runs.
+    fn run_with_full_capture(self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Like [`CmdExt::run_with_full_capture`], but returns the captured stdout.
+    fn read_with_capture(self) -> Result<String, Box<dyn std::error::Error>>;
 }
 
 impl CmdExt for Cmd<'_> {
-    fn run_with_capture(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn run_with_capture(self) -> Result<(), Box<dyn std::error::Error>> {
+        if plan::is_active() {
+            plan::record(self);
+            return Ok(());
+        }
+
         // In verbose mode, just run the command normally.
         if matches!(OutputMode::from_env(), OutputMode::Verbose) {
-            return Ok(self.run()?);
+            return self.run().map_err(|e| CommandError::new(&self, e.to_string()).into());
         }
 
         // In quiet/progress modes, capture output and only show on failure.
-        self.set_ignore_stdout(false);
-        self.set_ignore_stderr(false);
-        self.set_ignore_status(true);
+        run_captured(self, false).map(drop)
+    }
 
-        let output = self.output()?;
+    fn run_with_full_capture(self) -> Result<(), Box<dyn std::error::Error>> {
+        run_captured(self, matches!(OutputMode::from_env(), OutputMode::Verbose)).map(drop)
+    }
 
-        // Show output on failure.
-        if !output.status.success() {
-            eprint!("{}", String::from_utf8(output.stderr)?);
-            print!("{}", String::from_utf8(output.stdout)?);
-            return Err(format!("Command failed: {}", output.status).into());
-        }
+    fn read_with_capture(self) -> Result<String, Box<dyn std::error::Error>> {
+        run_captured(self, matches!(OutputMode::from_env(), OutputMode::Verbose))
+    }
+}
+
+/// Run a command with its output captured, showing it on failure or if `show` is set.
+///
+/// Returns the captured stdout, which is empty if the command is only planned.
+fn run_captured(mut cmd: Cmd<'_>, show: bool) -> Result<String, Box<dyn std::error::Error>> {
+    if plan::is_active() {
+        plan::record(cmd);
+        return Ok(String::new());
+    }
+
+    cmd.set_ignore_stdout(false);
+    cmd.set_ignore_stderr(false);
+    cmd.set_ignore_status(true);
+
+    let output = cmd.output().map_err(|e| CommandError::new(&cmd, e.to_string()))?;
+    let stdout = String::from_utf8(output.stdout)?;
+    let stderr = String::from_utf8(output.stderr)?;
+
+    // Show output on failure.
+    if show || !output.status.success() {
+        eprint!("{}", stderr);
+        print!("{}", stdout);
+    }
+    if !output.status.success() {
+        let mut error = CommandError::new(&cmd, format!("Command failed: {}", output.status));
+        error.code = output.status.code();
+        error.output = Some(CapturedOutput { stdout, stderr });
+        return Err(error.into());
+    }
+
+    Ok(stdout)
+}
+
+/// A command run by [`CmdExt::run_with_capture`] which failed.
+///
+/// Keeps the command line around so callers can report exactly what failed, e.g. by
+/// downcasting a boxed error.
+#[derive(Debug)]
+pub struct CommandError {
+    /// The command line, as displayed by xshell.
+    pub command: String,
+    /// Why the command failed.
+    message: String,
+    /// Exit code of the command, if it ran and its output was captured.
+    pub code: Option<i32>,
+    /// Output of the command, if it was captured.
+    pub output: Option<CapturedOutput>,
+}
+
+impl CommandError {
+    fn new(cmd: &Cmd<'_>, message: String) -> Self {
+        Self { command: cmd.to_string(), message, code: None, output: None }
+    }
+}
+
+/// Captured output of a failed command.
+#[derive(Debug)]
+pub struct CapturedOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
 
-        Ok(())
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
     }
 }
 
+impl std::error::Error for CommandError {}
+
 /// Guard that clears the progress line on stderr when dropped if in Progress mode.
 pub struct ProgressGuard {
     disabled: bool,
@@ -107,10 +188,16 @@ pub struct Package {
     /// Names of the package's direct, non-dev (normal and build), external and internal
     /// dependencies.
     pub deps: Vec<String>,
+    /// Names of the package's direct dev-dependencies.
+    pub dev_deps: Vec<String>,
     /// Features defined in the package's `[features]` table, excluding `"default"`
     /// since it cannot be passed directly to `--features`. Optional dependencies
     /// appear as implicit features.
     pub features: Vec<String>,
+    /// The package's `[features]` table (including `"default"` and implicit features), mapping
+    /// each feature to what it enables (`"feature"`, `"dep:name"`, `"name/feature"`, or
+    /// `"name?/feature"`).
+    pub feature_graph: BTreeMap<String, Vec<String>>,
     /// Whether the package can be published (`false` if the manifest sets `publish = false`).
     pub publish: bool,
 }
@@ -202,30 +289,43 @@ pub fn get_workspace_packages(
             let manifest_path = PathBuf::from(package["manifest_path"].as_str()?);
             // Metadata lists one entry per (dependency, kind, target)
             // combination, so the same crate can appear multiple times; sort
-            // and dedup for deterministic output. Dev-dependencies are excluded.
-            let mut deps: Vec<String> = package["dependencies"]
-                .as_array()?
-                .iter()
-                .filter(|d| d["kind"].as_str() != Some("dev"))
-                .filter_map(|d| d["name"].as_str().map(String::from))
-                .collect();
-            deps.sort();
-            deps.dedup();
+            // and dedup for deterministic output. Dev-dependencies are kept apart.
+            let dependencies = package["dependencies"].as_array()?;
+            let names = |dev: bool| {
+                let mut names: Vec<String> = dependencies
+                    .iter()
+                    .filter(|d| (d["kind"].as_str() == Some("dev")) == dev)
+                    .filter_map(|d| d["name"].as_str().map(String::from))
+                    .collect();
+                names.sort();
+                names.dedup();
+                names
+            };
+            let (deps, dev_deps) = (names(false), names(true));
             // Includes implicit features cargo synthesizes for optional dependencies,
             // `default` is excluded.
-            let features: Vec<String> = package["features"]
+            let feature_graph: BTreeMap<String, Vec<String>> = package["features"]
                 .as_object()?
-                .keys()
-                .filter(|k| *k != "default")
-                .cloned()
+                .iter()
+                .map(|(feature, enables)| {
+                    let enables = enables
+                        .as_array()
+                        .map(|e| e.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                        .unwrap_or_default();
+                    (feature.clone(), enables)
+                })
                 .collect();
+            let features: Vec<String> =
+                feature_graph.keys().filter(|k| *k != "default").cloned().collect();
 
             Some(Package {
                 name: package["name"].as_str()?.to_string(),
                 dir: manifest_path.parent()?.to_path_buf(),
                 id: package["id"].as_str()?.to_string(),
                 deps,
+                dev_deps,
                 features,
+                feature_graph,
                 // `publish = false` in a manifest is an empty array in metadata;
                 // a missing field or a list of registries both mean publishable.
                 publish: package["publish"].as_array() != Some(&vec![]),
@@ -279,7 +379,7 @@ pub fn get_workspace_packages(
 
         error_msg.push_str("\n\nAvailable packages (manifest name / directory):");
         for pkg in &all_packages {
-            error_msg.push_str(&format!("\n  - {} ({})", pkg.name, pkg.dir.display()));
+            let _ = write!(error_msg, "\n  - {} ({})", pkg.name, pkg.dir.display());
         }
 
         return Err(error_msg.into());
@@ -325,32 +425,160 @@ pub fn get_target_dir(sh: &Shell) -> Result<PathBuf, Box<dyn std::error::Error>>
 
 /// A minimal representation of a package manifest (`Cargo.toml`).
 ///
-/// Only fields not available via `cargo metadata` are included here. Prefer
-/// `cargo metadata` for all other package information since it is the stable,
-/// supported interface for querying package data.
+/// Only fields not available via `cargo metadata` are included here, or fields whose declared
+/// form matters, such as whether `edition` is set at all or a dependency's version requirement
+/// as written. Prefer `cargo metadata` for all other package information since it is the
+/// stable, supported interface for querying package data.
 pub struct Manifest {
     /// The `exclude` field from `[package]`, listing paths excluded from publishing.
     pub exclude: Vec<String>,
+    /// Whether the package inherits the workspace lints with `[lints] workspace = true`.
+    pub workspace_lints: bool,
+    /// Names of the features declared in `[features]`, without the implicit features of
+    /// optional dependencies which `cargo metadata` adds.
+    pub features: Vec<String>,
+    /// The `license` field from `[package]`, or `license-file` if only that is set.
+    pub license: Option<ManifestField>,
+    /// The `repository` field from `[package]`.
+    pub repository: Option<ManifestField>,
+    /// The `description` field from `[package]`.
+    pub description: Option<ManifestField>,
+    /// The `rust-version` field from `[package]`.
+    pub rust_version: Option<ManifestField>,
+    /// The `edition` field from `[package]`.
+    pub edition: Option<ManifestField>,
+    /// Dependencies declared in the normal, dev, and build dependency tables, including the
+    /// target-specific ones.
+    pub dependencies: Vec<DeclaredDependency>,
+}
+
+/// A `[package]` field which is either set in the manifest or inherited from
+/// `[workspace.package]` with `field.workspace = true`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ManifestField {
+    /// The value set in the manifest.
+    Value(String),
+    /// Inherited from `[workspace.package]`.
+    Inherited { workspace: bool },
+}
+
+/// A dependency as declared in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclaredDependency {
+    /// Name of the package depended on, which differs from the table key for renamed
+    /// dependencies.
+    pub name: String,
+    /// The version requirement, `None` for path or git dependencies without one and for
+    /// dependencies inherited from `[workspace.dependencies]`.
+    pub requirement: Option<String>,
+}
+
+impl DeclaredDependency {
+    /// Parse a `key = "1.0"` or `key = { package = "name", version = "1.0", ... }` entry.
+    fn parse(key: &str, value: &toml::Value) -> Self {
+        match value {
+            toml::Value::String(requirement) =>
+                Self { name: key.to_owned(), requirement: Some(requirement.clone()) },
+            _ => Self {
+                name: value.get("package").and_then(toml::Value::as_str).unwrap_or(key).to_owned(),
+                requirement: value.get("version").and_then(toml::Value::as_str).map(str::to_owned),
+            },
+        }
+    }
 }
 
 impl Manifest {
     /// Read and parse the `Cargo.toml` in the given package directory.
     pub fn read(package_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&fs::read_to_string(package_dir.join("Cargo.toml"))?)
+    }
+
+    /// Parse the contents of a package's `Cargo.toml`.
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
         #[derive(serde::Deserialize)]
         struct CargoToml {
             package: CargoPackage,
+            #[serde(default)]
+            lints: CargoLints,
+            #[serde(default)]
+            features: BTreeMap<String, toml::Value>,
+            #[serde(flatten)]
+            dependencies: DependencyTables,
+            #[serde(default)]
+            target: BTreeMap<String, DependencyTables>,
         }
 
         #[derive(serde::Deserialize)]
         struct CargoPackage {
             #[serde(default)]
             exclude: Vec<String>,
+            license: Option<ManifestField>,
+            #[serde(rename = "license-file")]
+            license_file: Option<ManifestField>,
+            repository: Option<ManifestField>,
+            description: Option<ManifestField>,
+            #[serde(rename = "rust-version")]
+            rust_version: Option<ManifestField>,
+            edition: Option<ManifestField>,
+        }
+
+        #[derive(serde::Deserialize, Default)]
+        struct CargoLints {
+            #[serde(default)]
+            workspace: bool,
         }
 
-        let contents = fs::read_to_string(package_dir.join("Cargo.toml"))?;
-        let cargo_toml: CargoToml = toml::from_str(&contents)?;
+        #[derive(serde::Deserialize, Default)]
+        struct DependencyTables {
+            #[serde(default)]
+            dependencies: BTreeMap<String, toml::Value>,
+            #[serde(default, rename = "dev-dependencies")]
+            dev_dependencies: BTreeMap<String, toml::Value>,
+            #[serde(default, rename = "build-dependencies")]
+            build_dependencies: BTreeMap<String, toml::Value>,
+        }
 
-        Ok(Self { exclude: cargo_toml.package.exclude })
+        let cargo_toml: CargoToml = toml::from_str(contents)?;
+
+        let dependencies = std::iter::once(&cargo_toml.dependencies)
+            .chain(cargo_toml.target.values())
+            .flat_map(|tables| {
+                tables
+                    .dependencies
+                    .iter()
+                    .chain(&tables.dev_dependencies)
+                    .chain(&tables.build_dependencies)
+            })
+            .map(|(key, value)| DeclaredDependency::parse(key, value))
+            .collect();
+
+        let package = cargo_toml.package;
+        Ok(Self {
+            exclude: package.exclude,
+            workspace_lints: cargo_toml.lints.workspace,
+            features: cargo_toml.features.into_keys().collect(),
+            license: package.license.or(package.license_file),
+            repository: package.repository,
+            description: package.description,
+            rust_version: package.rust_version,
+            edition: package.edition,
+            dependencies,
+        })
+    }
+
+    /// Whether `exclude` keeps the `api/` directory of API snapshots out of the published
+    /// package, e.g. with `"api"`, `"/api/"`, or `"api/**"`.
+    pub fn excludes_api_dir(&self) -> bool {
+        self.exclude.iter().any(|pattern| {
+            let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+            let dir = pattern
+                .strip_suffix("/**")
+                .or_else(|| pattern.strip_suffix("/*"))
+                .or_else(|| pattern.strip_suffix('/'))
+                .unwrap_or(pattern);
+            dir == "api"
+        })
     }
 }
 
@@ -2,7 +2,9 @@
 
 //! Git utilities for switching refs and enumerating commits.
 
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -134,6 +136,43 @@ pub fn current_commit_id(sh: &Shell) -> Option<String> {
     sh.cmd("git").args(["rev-parse", "HEAD"]).quiet().read().ok().map(|s| s.trim().to_owned())
 }
 
+/// Identify the contents of the given files and directories in the working tree.
+///
+/// Combines the object IDs of the paths at HEAD with a hash of any uncommitted changes to tracked
+/// files under them and of the untracked (but not ignored) files under them, so commits which only
+/// differ elsewhere or in metadata (e.g. a reworded message) share an ID while local edits do not.
+/// Returns `None` if the working directory is not inside a git repository or a path is not tracked
+/// at HEAD.
+pub fn content_id(sh: &Shell, paths: &[PathBuf]) -> Option<String> {
+    let mut objects = Vec::new();
+    for path in paths {
+        // `HEAD:./<name>` is resolved relative to the directory git runs in.
+        let (dir, name) = if path.is_dir() {
+            (path.as_path(), String::new())
+        } else {
+            (path.parent()?, path.file_name()?.to_string_lossy().into_owned())
+        };
+        let object = format!("HEAD:./{}", name);
+        let id = sh.cmd("git").arg("-C").arg(dir).args(["rev-parse", &object]);
+        objects.push(id.quiet().ignore_stderr().read().ok()?);
+    }
+    let diff = sh.cmd("git").args(["diff", "HEAD", "--"]).args(paths);
+    let diff = diff.quiet().ignore_stderr().read().ok()?;
+    let untracked = sh.cmd("git").args(["ls-files", "--others", "--exclude-standard", "--"]);
+    let untracked = untracked.args(paths).quiet().ignore_stderr().read().ok()?;
+
+    let mut hasher = DefaultHasher::new();
+    objects.hash(&mut hasher);
+    diff.hash(&mut hasher);
+    // Listed relative to the directory git runs in.
+    let dir = sh.current_dir();
+    for file in untracked.lines() {
+        file.hash(&mut hasher);
+        std::fs::read(dir.join(file)).ok()?.hash(&mut hasher);
+    }
+    Some(format!("{:016x}", hasher.finish()))
+}
+
 /// Returns `true` if any file under the given path differs from the baseline git ref.
 pub fn has_changes_since(
     sh: &Shell,
@@ -144,6 +183,31 @@ pub fn has_changes_since(
     Ok(!output.trim().is_empty())
 }
 
+/// Files which changed since the branch point with the baseline git ref, including untracked
+/// files, as absolute paths.
+///
+/// As with [`list_commits`], changes which only landed on the baseline since the branch point
+/// are left out.
+pub fn changed_files(
+    sh: &Shell,
+    baseline: &str,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let toplevel = rbmt_cmd!(sh, "git rev-parse --show-toplevel").read()?;
+    let toplevel = toplevel.trim();
+    let merge_base = rbmt_cmd!(sh, "git merge-base HEAD {baseline}").read()?;
+    let merge_base = merge_base.trim();
+    let changed = rbmt_cmd!(sh, "git diff --name-only {merge_base}").read()?;
+    // Untracked files are listed relative to the directory git runs in.
+    let untracked =
+        rbmt_cmd!(sh, "git -C {toplevel} ls-files --others --exclude-standard").read()?;
+    Ok(changed
+        .lines()
+        .chain(untracked.lines())
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Path::new(toplevel).join(line))
+        .collect())
+}
+
 /// List the commits between the given base ref and HEAD, oldest first.
 pub fn list_commits(sh: &Shell, base: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let range_base = rbmt_cmd!(sh, "git merge-base HEAD {base}").read()?;
@@ -192,3 +256,124 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn content_id_follows_paths() {
+        let dir = std::env::temp_dir().join(format!("rbmt-content-id-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for package in ["a", "b"] {
+            fs::create_dir_all(dir.join(package)).unwrap();
+            fs::write(dir.join(package).join("lib.rs"), "").unwrap();
+        }
+        fs::write(dir.join("Cargo.toml"), "[workspace]\n").unwrap();
+
+        let sh = Shell::new().unwrap();
+        sh.change_dir(&dir);
+        let git = |args: &[&str]| {
+            sh.cmd("git")
+                .args(["-c", "user.name=rbmt", "-c", "user.email=rbmt@example.com"])
+                .args(args)
+                .quiet()
+                .ignore_stdout()
+                .ignore_stderr()
+                .run()
+                .unwrap();
+        };
+        let paths = [dir.join("a"), dir.join("Cargo.toml")];
+        git(&["init", "-q"]);
+        // Nothing is tracked before the first commit.
+        assert_eq!(content_id(&sh, &paths), None);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "initial"]);
+        let initial = content_id(&sh, &paths).unwrap();
+        assert_eq!(content_id(&sh, &paths).unwrap(), initial);
+
+        // Changes outside the paths and to the commit message keep the ID.
+        fs::write(dir.join("b").join("lib.rs"), "fn b() {}").unwrap();
+        assert_eq!(content_id(&sh, &paths).unwrap(), initial);
+        git(&["commit", "-q", "-a", "-m", "change b"]);
+        git(&["commit", "-q", "--amend", "-m", "reworded"]);
+        assert_eq!(content_id(&sh, &paths).unwrap(), initial);
+
+        // Uncommitted and committed changes to the paths do not.
+        fs::write(dir.join("a").join("lib.rs"), "fn a() {}").unwrap();
+        let edited = content_id(&sh, &paths).unwrap();
+        assert_ne!(edited, initial);
+        git(&["commit", "-q", "-a", "-m", "change a"]);
+        let committed = content_id(&sh, &paths).unwrap();
+        assert_ne!(committed, initial);
+        assert_ne!(committed, edited);
+        fs::write(dir.join("Cargo.toml"), "[workspace]\nmembers = []\n").unwrap();
+        let manifest_edited = content_id(&sh, &paths).unwrap();
+        assert_ne!(manifest_edited, committed);
+
+        // So do untracked files under the paths, unless they are ignored.
+        fs::write(dir.join("a").join("new.rs"), "fn new() {}").unwrap();
+        let untracked = content_id(&sh, &paths).unwrap();
+        assert_ne!(untracked, manifest_edited);
+        fs::write(dir.join("a").join("new.rs"), "fn newer() {}").unwrap();
+        assert_ne!(content_id(&sh, &paths).unwrap(), untracked);
+        fs::write(dir.join(".gitignore"), "*.rs.bk\n").unwrap();
+        let ignored = content_id(&sh, &paths).unwrap();
+        fs::write(dir.join("a").join("lib.rs.bk"), "").unwrap();
+        assert_eq!(content_id(&sh, &paths).unwrap(), ignored);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changed_files_since_branch_point() {
+        let dir = std::env::temp_dir().join(format!("rbmt-changed-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for package in ["a", "b"] {
+            fs::create_dir_all(dir.join(package)).unwrap();
+            fs::write(dir.join(package).join("lib.rs"), "").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*.rs.bk\n").unwrap();
+
+        let sh = Shell::new().unwrap();
+        sh.change_dir(&dir);
+        let git = |args: &[&str]| {
+            sh.cmd("git")
+                .args(["-c", "user.name=rbmt", "-c", "user.email=rbmt@example.com"])
+                .args(args)
+                .quiet()
+                .ignore_stdout()
+                .ignore_stderr()
+                .run()
+                .unwrap();
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "initial"]);
+        git(&["branch", "base"]);
+        git(&["switch", "-q", "-c", "topic"]);
+        fs::write(dir.join("a").join("lib.rs"), "fn a() {}").unwrap();
+        git(&["commit", "-q", "-a", "-m", "change a"]);
+
+        // Changes which only landed on the baseline since the branch point are not ours.
+        git(&["switch", "-q", "base"]);
+        fs::write(dir.join("b").join("lib.rs"), "fn b() {}").unwrap();
+        git(&["commit", "-q", "-a", "-m", "change b"]);
+        git(&["switch", "-q", "topic"]);
+        let dir = dir.canonicalize().unwrap();
+        assert_eq!(changed_files(&sh, "base").unwrap(), [dir.join("a").join("lib.rs")]);
+
+        // Untracked files are included, unless they are ignored.
+        fs::write(dir.join("b").join("new.rs"), "").unwrap();
+        fs::write(dir.join("b").join("lib.rs.bk"), "").unwrap();
+        sh.change_dir(dir.join("a"));
+        assert_eq!(
+            changed_files(&sh, "base").unwrap(),
+            [dir.join("a").join("lib.rs"), dir.join("b").join("new.rs")]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
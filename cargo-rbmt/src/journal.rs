@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! On-disk journal of completed units of work.
+//!
+//! Long runs, like testing every commit of a branch, record each unit of work as it passes so a
+//! later run can skip what has already been done. A unit is an opaque key chosen by the caller,
+//! which should encode everything that could change the outcome (commit, lockfile, toolchain...).
+//! The journal is a plain text file with one key per line and is only ever appended to, so an
+//! interrupted run leaves a usable journal behind.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Journal of completed units, safe to record to from multiple threads.
+pub struct Journal {
+    /// Units completed by previous runs.
+    completed: HashSet<String>,
+    /// Append handle for recording newly completed units, `None` if read-only.
+    file: Option<Mutex<File>>,
+}
+
+impl Journal {
+    /// Open the journal at `path`.
+    ///
+    /// If `resume` is `true`, units recorded by previous runs are loaded and reported by
+    /// [`Journal::contains`]. Otherwise the journal is truncated and the run starts fresh.
+    pub fn open(path: &Path, resume: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let completed = if resume && path.exists() {
+            fs::read_to_string(path)?.lines().map(str::to_owned).collect()
+        } else {
+            HashSet::new()
+        };
+
+        let file = OpenOptions::new().create(true).append(true).truncate(false).open(path)?;
+        if !resume {
+            file.set_len(0)?;
+        }
+
+        Ok(Self { completed, file: Some(Mutex::new(file)) })
+    }
+
+    /// Open the journal at `path` without truncating it, recording nothing.
+    ///
+    /// If `resume` is `true`, units recorded by previous runs are reported by
+    /// [`Journal::contains`], so a plan leaves them out like a real run would.
+    pub fn read_only(path: &Path, resume: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let completed = if resume && path.exists() {
+            fs::read_to_string(path)?.lines().map(str::to_owned).collect()
+        } else {
+            HashSet::new()
+        };
+        Ok(Self { completed, file: None })
+    }
+
+    /// Returns `true` if the unit was completed by a previous run.
+    pub fn contains(&self, unit: &str) -> bool { self.completed.contains(unit) }
+
+    /// Record a completed unit.
+    pub fn record(&self, unit: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(file) = &self.file else { return Ok(()) };
+        let mut file = file.lock().map_err(|_| "Journal lock poisoned")?;
+        writeln!(file, "{}", unit)?;
+        // Flush each unit so an interrupted run keeps everything completed so far.
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Hash arbitrary values into a short hex fingerprint for use in unit keys.
+///
+/// The fingerprint is stable for a given build of rbmt, which is all a journal needs. A new rbmt
+/// version may hash differently, in which case previously completed units are simply re-run.
+pub fn fingerprint(values: &[&str]) -> String {
+    let mut hasher = DefaultHasher::new();
+    values.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A journal path of its own for each test, in a fresh directory.
+    fn journal_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rbmt-journal-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("rbmt").join("test-journal")
+    }
+
+    #[test]
+    fn recorded_units_are_resumed() {
+        let path = journal_path("resume");
+        let journal = Journal::open(&path, true).unwrap();
+        assert!(!journal.contains("a"));
+        journal.record("a").unwrap();
+        journal.record("b").unwrap();
+        // Only units of previous runs are reported.
+        assert!(!journal.contains("a"));
+
+        let journal = Journal::open(&path, true).unwrap();
+        assert!(journal.contains("a") && journal.contains("b"));
+        journal.record("c").unwrap();
+        let read_only = Journal::read_only(&path, true).unwrap();
+        assert!(read_only.contains("a") && read_only.contains("c"));
+        read_only.record("d").unwrap();
+        assert!(!Journal::read_only(&path, true).unwrap().contains("d"));
+        assert!(!Journal::read_only(&path, false).unwrap().contains("a"));
+
+        // Without resume the journal starts afresh.
+        let journal = Journal::open(&path, false).unwrap();
+        assert!(!journal.contains("a"));
+        journal.record("e").unwrap();
+        let journal = Journal::open(&path, true).unwrap();
+        assert!(!journal.contains("a") && !journal.contains("c"));
+        assert!(journal.contains("e"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "e\n");
+    }
+
+    #[test]
+    fn fingerprint_depends_on_values() {
+        assert_eq!(fingerprint(&["lock", "nightly"]), fingerprint(&["lock", "nightly"]));
+        assert_ne!(fingerprint(&["lock", "nightly"]), fingerprint(&["lock", "stable"]));
+        // Values are hashed separately, not concatenated.
+        assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+        assert_eq!(fingerprint(&[]).len(), 16);
+    }
+}
@@ -1,17 +1,18 @@
 // SPDX-License-Identifier: MIT AND Apache-2.0
 
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
 use xshell::Shell;
 
 use crate::environment::{
-    cargo_cmd, get_workspace_packages, get_workspace_root, CmdExt, Package, PackageManifest,
-    ProgressGuard,
+    cargo_cmd, get_workspace_packages, get_workspace_root, CmdExt, Manifest, ManifestField,
+    Package, PackageManifest, ProgressGuard, WorkspaceManifest,
 };
 use crate::lock::LockFile;
 use crate::toolchain::{prepare_toolchain, Toolchain};
+use crate::{api, git, plan, test};
 
 /// Cargo tree arguments for duplicate dependency detection.
 const CARGO_TREE_ARGS: &[&str] = &[
@@ -37,8 +38,24 @@ enum LintError {
     DuplicateDependencies(Vec<(String, String)>), // (package_name, tree_output)
     /// Stale entries in `allowed_duplicates` configuration.
     StaleAllowedDuplicates(Vec<(String, Vec<String>)>), // (package_name, stale_entries)
+    /// Versions of the same crate pulled in by different workspace members.
+    CrossPackageDuplicateDependencies(Vec<String>), // "crate version: members"
+    /// Stale entries in `allowed_cross_package_duplicates` configuration.
+    StaleAllowedCrossPackageDuplicates(Vec<String>), // stale_entries
     /// Deprecated MSRV settings found in clippy.toml files.
     DeprecatedClippyMsrv(Vec<String>), // file_paths
+    /// Packages which do not inherit the workspace lints.
+    MissingWorkspaceLints(Vec<String>), // package_names
+    /// The `[workspace.lints.clippy]` table is out of date with the pinned nightly's clippy.
+    OutdatedClippyLints { missing: Vec<String>, unknown: Vec<String> },
+    /// Dependencies no target of the package uses.
+    UnusedDependencies(Vec<(String, Vec<String>)>), // (package_name, dependencies)
+    /// Stale entries in `allowed_unused_dependencies` configuration.
+    StaleAllowedUnusedDependencies(Vec<(String, Vec<String>)>), // (package_name, stale_entries)
+    /// Mistakes in the `[features]` table or the features named by the test configuration.
+    FeatureProblems(Vec<(String, Vec<String>)>), // (package_name, problems)
+    /// Package manifests which do not follow the workspace policy.
+    ManifestPolicy(Vec<String>), // problems
 }
 
 impl std::fmt::Display for LintError {
@@ -60,6 +77,20 @@ impl std::fmt::Display for LintError {
                 }
                 Ok(())
             }
+            Self::CrossPackageDuplicateDependencies(duplicates) => {
+                write!(f, "Found cross-package duplicate dependencies")?;
+                for duplicate in duplicates {
+                    write!(f, "\n  {}", duplicate)?;
+                }
+                Ok(())
+            }
+            Self::StaleAllowedCrossPackageDuplicates(entries) => {
+                write!(f, "Stale entries in `allowed_cross_package_duplicates` found")?;
+                for entry in entries {
+                    write!(f, "\n  {}", entry)?;
+                }
+                Ok(())
+            }
             Self::DeprecatedClippyMsrv(files) => {
                 write!(
                     f,
@@ -70,6 +101,60 @@ impl std::fmt::Display for LintError {
                 }
                 Ok(())
             }
+            Self::MissingWorkspaceLints(packages) => {
+                write!(
+                    f,
+                    "Packages do not inherit the workspace lints, add `[lints] workspace = true`"
+                )?;
+                for package in packages {
+                    write!(f, "\n  {}", package)?;
+                }
+                Ok(())
+            }
+            Self::OutdatedClippyLints { missing, unknown } => {
+                write!(f, "[workspace.lints.clippy] is out of date with the pinned nightly")?;
+                for lint in missing {
+                    write!(f, "\n  missing pedantic lint: {}", lint)?;
+                }
+                for lint in unknown {
+                    write!(f, "\n  lint no longer exists: {}", lint)?;
+                }
+                Ok(())
+            }
+            Self::UnusedDependencies(unused) => {
+                write!(f, "Found unused dependencies, remove them or add them to `allowed_unused_dependencies`")?;
+                for (pkg_name, dependencies) in unused {
+                    for dependency in dependencies {
+                        write!(f, "\n  {}: {}", pkg_name, dependency)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::FeatureProblems(problems) => {
+                write!(f, "Found problems with package features")?;
+                for (pkg_name, problems) in problems {
+                    for problem in problems {
+                        write!(f, "\n  {}: {}", pkg_name, problem)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::ManifestPolicy(problems) => {
+                write!(f, "Found package manifests which do not follow the workspace policy")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            Self::StaleAllowedUnusedDependencies(stale_entries) => {
+                write!(f, "Stale entries in `allowed_unused_dependencies` found")?;
+                for (pkg_name, entries) in stale_entries {
+                    for entry in entries {
+                        write!(f, "\n  {}: {}", pkg_name, entry)?;
+                    }
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -82,6 +167,11 @@ impl std::error::Error for LintError {}
 struct LintConfig {
     /// List of crate names that are allowed to have duplicate versions.
     allowed_duplicates: Vec<String>,
+    /// List of dependencies which are only used behind `cfg`s the feature matrix does not build.
+    allowed_unused_dependencies: Vec<String>,
+    /// Whether to check unused dependencies with every feature set of the `test` matrix, instead
+    /// of only no, default, and all features. Defaults to `false`.
+    unused_dependencies_matrix: bool,
 }
 
 impl LintConfig {
@@ -102,6 +192,32 @@ impl LintConfig {
     }
 }
 
+/// Workspace-wide lint configuration, read from `[workspace.metadata.rbmt.lint]` in the root
+/// `Cargo.toml`.
+#[derive(Debug, serde::Deserialize, Default)]
+#[serde(default)]
+struct WorkspaceLintConfig {
+    /// List of crate names that workspace members are allowed to depend on different versions of.
+    allowed_cross_package_duplicates: Vec<String>,
+    /// List of dependencies which workspace members are allowed to declare with different
+    /// version requirements.
+    allowed_requirement_mismatches: Vec<String>,
+}
+
+impl WorkspaceLintConfig {
+    /// Load lint configuration from `[workspace.metadata.rbmt.lint]` in the root `Cargo.toml`.
+    fn load(workspace_root: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(serde::Deserialize, Default)]
+        struct RbmtTable {
+            #[serde(default)]
+            lint: WorkspaceLintConfig,
+        }
+
+        let contents = std::fs::read_to_string(workspace_root.join("Cargo.toml"))?;
+        Ok(toml::from_str::<WorkspaceManifest<RbmtTable>>(&contents)?.workspace.metadata.rbmt.lint)
+    }
+}
+
 /// Run the lint task.
 pub fn run(
     sh: &Shell,
@@ -116,9 +232,22 @@ pub fn run(
 
     lint_workspace(sh)?;
     lint_packages(sh, &packages)?;
+    // The remaining checks only inspect the dependency tree, the manifests, and the lints of
+    // `clippy-driver -W help`, so they build nothing and are left out of the plan. The unused
+    // dependency check runs clippy for every feature set, so it is planned like the runs above.
+    if plan::is_active() {
+        rbmt_eprintln!(
+            "Leaving the dependency, manifest, and workspace lint checks out of the plan"
+        );
+        return check_unused_deps(sh, &packages);
+    }
     check_duplicate_deps(sh, &packages)?;
     check_cross_package_duplicate_deps(sh)?;
     check_clippy_toml_msrv(sh, &packages)?;
+    check_features(&packages)?;
+    check_manifest_policy(sh, &packages)?;
+    check_workspace_lints(sh, &packages)?;
+    check_unused_deps(sh, &packages)?;
 
     rbmt_eprintln!("Lint task completed successfully");
     Ok(())
@@ -258,12 +387,10 @@ fn check_duplicate_deps(
 /// Dev dependencies are excluded from this check because they are not part of the published
 /// crate graph and cannot cause problems for downstream consumers.
 ///
-/// This check is not considered as essential as [`check_duplicate_deps`]. A duplicate dependency
-/// in a single package has a much higher chance of causing downstream issues than one across
-/// packages since it may not be an issue depending on what versions of the packages downstream
-/// users are using. This functionality could probably also be accomplished just with
-/// [`check_duplicate_deps`] if a workspace contains a facade package which re-exports all
-/// the other packages of the workspace.
+/// A duplicate dependency across packages is less likely to cause downstream issues than one in a
+/// single package, since it depends on which versions of the packages downstream users pick.
+/// Duplicates which are expected, e.g. while members migrate to a new version one at a time, can
+/// be allowed workspace-wide with `[workspace.metadata.rbmt.lint] allowed_cross_package_duplicates`.
 fn check_cross_package_duplicate_deps(sh: &Shell) -> Result<(), Box<dyn std::error::Error>> {
     let package_info = get_workspace_packages(sh, &[])?;
 
@@ -277,23 +404,37 @@ fn check_cross_package_duplicate_deps(sh: &Shell) -> Result<(), Box<dyn std::err
     // Run on all workspace members with the `--workspace` flag.
     let output = cargo_cmd(sh).args(CARGO_TREE_ARGS).arg("--workspace").ignore_status().read()?;
 
-    let tree = DuplicateTree::parse(&output, &[]);
+    let config = WorkspaceLintConfig::load(&get_workspace_root(sh)?)?;
+    let tree = DuplicateTree::parse(&output, &config.allowed_cross_package_duplicates);
     let cross_package_dupes = tree.cross_package_duplicates();
-    // Currently logging a warning instead of hard failure until we gain confidence in the check.
     if !cross_package_dupes.is_empty() {
-        rbmt_eprintln!("Found {} cross-package duplicate dependencies", cross_package_dupes.len());
+        let mut duplicates = Vec::new();
         for (crate_name, versions) in &cross_package_dupes {
             for (version, members) in *versions {
                 let members: Vec<&str> = members.iter().map(String::as_str).collect();
-                rbmt_eprintln!("  {} {}: {}", crate_name, version, members.join(", "));
+                duplicates.push(format!("{} {}: {}", crate_name, version, members.join(", ")));
             }
         }
+        return Err(Box::new(LintError::CrossPackageDuplicateDependencies(duplicates)));
+    }
+    let stale =
+        stale_allowed_cross_package_duplicates(&output, &config.allowed_cross_package_duplicates);
+    if !stale.is_empty() {
+        return Err(Box::new(LintError::StaleAllowedCrossPackageDuplicates(stale)));
     }
 
     rbmt_eprintln!("No cross-package duplicate dependencies found");
     Ok(())
 }
 
+/// Entries of `allowed` which are no longer cross-package duplicates in the `cargo tree`
+/// output, even if the crate is still duplicated within a single package.
+fn stale_allowed_cross_package_duplicates(output: &str, allowed: &[String]) -> Vec<String> {
+    let tree = DuplicateTree::parse(output, &[]);
+    let cross_package_dupes = tree.cross_package_duplicates();
+    allowed.iter().filter(|a| !cross_package_dupes.contains_key(a.as_str())).cloned().collect()
+}
+
 /// A dependency from `cargo tree --duplicates --prefix depth` output.
 struct Dependency {
     /// Depth-0 lines are the duplicate crates themselves; all lines beneath them (at any
@@ -581,6 +722,697 @@ fn check_clippy_toml_msrv(
     Ok(())
 }
 
+/// Check the features of each package for mistakes which are easy to ship.
+///
+/// * A feature which no `cfg` checks and no other feature enables does nothing, unless it enables
+///   a dependency or a feature which is checked.
+/// * An optional dependency which no feature enables with `dep:` is exposed as an implicit
+///   feature, making it part of the public API.
+/// * `std` should enable `alloc` when a package has both, since code gated on `alloc` is
+///   expected to be available with `std`.
+/// * `exact_features` and `msrv_overrides` should only name existing features.
+fn check_features(packages: &[Package]) -> Result<(), Box<dyn std::error::Error>> {
+    rbmt_eprintln!("Checking package features...");
+
+    let mut problems = Vec::new();
+    for package in packages {
+        let manifest = Manifest::read(&package.dir)?;
+        let referenced = referenced_features(&package.dir)?;
+        let configured = test::TestConfig::load(&package.dir)?;
+        let found =
+            feature_problems(&package.feature_graph, &manifest.features, &referenced, &configured);
+        if !found.is_empty() {
+            problems.push((package.name.clone(), found));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(Box::new(LintError::FeatureProblems(problems)));
+    }
+
+    rbmt_eprintln!("No problems with package features found");
+    Ok(())
+}
+
+/// Problems with the features of a package, see [`check_features`].
+///
+/// `graph` is the `[features]` table as reported by `cargo metadata`, including implicit
+/// features, `declared` the features of the manifest, and `referenced` the features checked by
+/// the package's sources.
+fn feature_problems(
+    graph: &BTreeMap<String, Vec<String>>,
+    declared: &[String],
+    referenced: &BTreeSet<String>,
+    configured: &test::TestConfig,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for feature in declared.iter().filter(|f| *f != "default") {
+        let enabled_by_feature =
+            graph.iter().any(|(other, enables)| other != feature && enables.contains(feature));
+        let has_effect = test::resolve_features(graph, Some(std::slice::from_ref(feature)))
+            .iter()
+            .any(|item| item.contains(':') || item.contains('/') || referenced.contains(item));
+        if !enabled_by_feature && !has_effect {
+            problems.push(format!(
+                "feature `{}` is never referenced by a `cfg` or another feature",
+                feature
+            ));
+        }
+    }
+
+    for feature in graph.keys().filter(|f| !declared.contains(f)) {
+        problems.push(format!(
+            "optional dependency `{}` is exposed as an implicit feature, enable it with `dep:{}`",
+            feature, feature
+        ));
+    }
+
+    let declares = |feature: &str| declared.iter().any(|f| f == feature);
+    if declares("std")
+        && declares("alloc")
+        && !test::resolve_features(graph, Some(&["std".to_owned()])).contains("alloc")
+    {
+        problems.push("feature `std` does not enable `alloc`".to_owned());
+    }
+
+    let exact_features: BTreeSet<String> =
+        configured.exact_features().concat().into_iter().collect();
+    for (setting, features) in [
+        ("exact_features", exact_features),
+        ("msrv_overrides", configured.msrv_overrides().into_keys().collect()),
+    ] {
+        // Features of dependencies, e.g. `serde/std`, are left to cargo.
+        for feature in features.into_iter().filter(|f| !f.contains('/') && !graph.contains_key(f)) {
+            problems
+                .push(format!("`{}` names feature `{}` which does not exist", setting, feature));
+        }
+    }
+
+    problems
+}
+
+/// Features referenced by the Rust sources of a package, by `feature = "name"` in a `cfg`,
+/// `cfg_attr`, or `doc(cfg)`, or by `CARGO_FEATURE_NAME` in a build script.
+///
+/// Every `.rs` file under the package directory is searched, except in `target/`, hidden
+/// directories, and nested packages.
+fn referenced_features(package_dir: &Path) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let manifest = Manifest::read(package_dir)?;
+    let mut referenced = BTreeSet::new();
+    let mut dirs_to_visit = vec![package_dir.to_path_buf()];
+    while let Some(dir) = dirs_to_visit.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                if name != "target" && !name.starts_with('.') && !path.join("Cargo.toml").exists() {
+                    dirs_to_visit.push(path);
+                }
+            } else if path.extension().and_then(|s| s.to_str()) == Some("rs") {
+                let source = fs::read_to_string(&path)?;
+                referenced.extend(cfg_features(&source).into_iter().map(str::to_owned));
+                referenced.extend(
+                    manifest
+                        .features
+                        .iter()
+                        .filter(|feature| {
+                            let var = feature.to_uppercase().replace('-', "_");
+                            source.contains(&format!("CARGO_FEATURE_{}", var))
+                        })
+                        .cloned(),
+                );
+            }
+        }
+    }
+    Ok(referenced)
+}
+
+/// Names in `feature = "name"` predicates of the source.
+///
+/// Keys which merely end in `feature`, such as `target_feature`, are not predicates on features.
+fn cfg_features(source: &str) -> Vec<&str> {
+    let mut features = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("feature") {
+        let preceded_by_ident =
+            rest[..start].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_');
+        rest = &rest[start + "feature".len()..];
+        if preceded_by_ident {
+            continue;
+        }
+        let Some(value) = rest.trim_start().strip_prefix('=') else { continue };
+        let Some(value) = value.trim_start().strip_prefix('"') else { continue };
+        if let Some((name, _)) = value.split_once('"') {
+            features.push(name);
+        }
+    }
+    features
+}
+
+/// What the manifest policy checks need to know about a package.
+struct ManifestMember {
+    name: String,
+    publish: bool,
+    /// Whether the package keeps API snapshots in `api/`.
+    api_snapshot: bool,
+    manifest: Manifest,
+}
+
+/// Check the manifests of the packages follow the workspace policy.
+///
+/// * Publishable packages declare `license` (or `license-file`), `repository`, `description`,
+///   `rust-version`, and `edition`.
+/// * `edition` and `rust-version` are the same in every package which declares them.
+/// * Packages keeping API snapshots exclude `api/` from publishing.
+/// * A dependency is declared with the same version requirement in every package, unless it is
+///   in `allowed_requirement_mismatches`. Equivalent spellings of a caret requirement, such as
+///   `1.0` and `^1.0.0`, are the same requirement.
+///
+/// Fields inherited with `field.workspace = true` take their value from `[workspace.package]`.
+fn check_manifest_policy(
+    sh: &Shell,
+    packages: &[Package],
+) -> Result<(), Box<dyn std::error::Error>> {
+    rbmt_eprintln!("Checking package manifests...");
+
+    let workspace_root = get_workspace_root(sh)?;
+    let contents = fs::read_to_string(workspace_root.join("Cargo.toml"))?;
+    let root: toml::Value = toml::from_str(&contents)?;
+    let workspace_package = root
+        .get("workspace")
+        .and_then(|w| w.get("package"))
+        .and_then(toml::Value::as_table)
+        .cloned()
+        .unwrap_or_default();
+    let config = WorkspaceLintConfig::load(&workspace_root)?;
+
+    let mut members = Vec::new();
+    for package in packages {
+        members.push(ManifestMember {
+            name: package.name.clone(),
+            publish: package.publish,
+            api_snapshot: api::snapshot_enabled(&package.dir)?,
+            manifest: Manifest::read(&package.dir)?,
+        });
+    }
+
+    let problems =
+        manifest_problems(&members, &workspace_package, &config.allowed_requirement_mismatches);
+    if !problems.is_empty() {
+        return Err(Box::new(LintError::ManifestPolicy(problems)));
+    }
+
+    rbmt_eprintln!("Package manifests follow the workspace policy");
+    Ok(())
+}
+
+/// Problems with the manifests of the packages, see [`check_manifest_policy`].
+fn manifest_problems(
+    members: &[ManifestMember],
+    workspace_package: &toml::Table,
+    allowed_mismatches: &[String],
+) -> Vec<String> {
+    let resolve = |field: Option<&ManifestField>, key: &str| match field? {
+        ManifestField::Value(value) => Some(value.clone()),
+        ManifestField::Inherited { workspace } =>
+            workspace_package.get(key).filter(|_| *workspace)?.as_str().map(str::to_owned),
+    };
+
+    let mut problems = Vec::new();
+    // Packages by value of the fields which should agree, and by requirement of each dependency.
+    let mut editions: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    let mut rust_versions: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    let mut requirements: BTreeMap<&str, BTreeMap<String, BTreeSet<&str>>> = BTreeMap::new();
+
+    for member in members {
+        let manifest = &member.manifest;
+        let name = member.name.as_str();
+        let license = resolve(manifest.license.as_ref(), "license")
+            .or_else(|| resolve(manifest.license.as_ref(), "license-file"));
+        let edition = resolve(manifest.edition.as_ref(), "edition");
+        let rust_version = resolve(manifest.rust_version.as_ref(), "rust-version");
+
+        if member.publish {
+            let missing: Vec<String> = [
+                ("license", license.is_some()),
+                ("repository", resolve(manifest.repository.as_ref(), "repository").is_some()),
+                ("description", resolve(manifest.description.as_ref(), "description").is_some()),
+                ("rust-version", rust_version.is_some()),
+                ("edition", edition.is_some()),
+            ]
+            .into_iter()
+            .filter(|(_, declared)| !declared)
+            .map(|(field, _)| format!("`{}`", field))
+            .collect();
+            if !missing.is_empty() {
+                problems.push(format!("{}: missing {}", name, missing.join(", ")));
+            }
+        }
+
+        if member.api_snapshot && !manifest.excludes_api_dir() {
+            problems.push(format!("{}: `exclude` does not cover the `api/` snapshots", name));
+        }
+
+        if let Some(edition) = edition {
+            editions.entry(edition).or_default().insert(name);
+        }
+        if let Some(rust_version) = rust_version {
+            rust_versions.entry(rust_version).or_default().insert(name);
+        }
+        for dependency in &manifest.dependencies {
+            if let Some(requirement) = &dependency.requirement {
+                requirements
+                    .entry(&dependency.name)
+                    .or_default()
+                    .entry(normalize_requirement(requirement))
+                    .or_default()
+                    .insert(name);
+            }
+        }
+    }
+
+    for (field, values) in [("edition", &editions), ("rust-version", &rust_versions)] {
+        if values.len() > 1 {
+            problems.push(format!(
+                "`{}` differs across packages: {}",
+                field,
+                packages_by_value(values)
+            ));
+        }
+    }
+    for (dependency, by_requirement) in &requirements {
+        if by_requirement.len() > 1 && !allowed_mismatches.iter().any(|a| a == dependency) {
+            problems.push(format!(
+                "dependency `{}` has different version requirements: {}",
+                dependency,
+                packages_by_value(by_requirement)
+            ));
+        }
+    }
+    for allowed in allowed_mismatches {
+        if requirements.get(allowed.as_str()).is_none_or(|by_requirement| by_requirement.len() < 2)
+        {
+            problems.push(format!(
+                "stale entry `{}` in `allowed_requirement_mismatches`, every package declares the \
+                 same requirement",
+                allowed
+            ));
+        }
+    }
+
+    problems
+}
+
+/// The canonical form of a version requirement, so that equivalent spellings of a caret
+/// requirement compare equal, e.g. `1.0`, `^1`, and `1.0.0` are all `1.0.0`.
+///
+/// Other requirements are only trimmed. Missing components of a requirement which is all zeros
+/// are not padded, since `^0.0` allows `0.0.1` but `^0.0.0` does not.
+fn normalize_requirement(requirement: &str) -> String {
+    let requirement = requirement.trim();
+    let version = requirement.strip_prefix('^').unwrap_or(requirement).trim();
+    let Ok(mut parts) = version.split('.').map(str::parse::<u64>).collect::<Result<Vec<_>, _>>()
+    else {
+        return requirement.to_owned();
+    };
+    if parts.len() > 3 || parts.iter().all(|part| *part == 0) {
+        return requirement.to_owned();
+    }
+    parts.resize(3, 0);
+    parts.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+/// Each value with the packages using it, e.g. `2018 (a), 2021 (b, c)`.
+fn packages_by_value<V: std::fmt::Display>(values: &BTreeMap<V, BTreeSet<&str>>) -> String {
+    values
+        .iter()
+        .map(|(value, packages)| {
+            format!("{} ({})", value, packages.iter().copied().collect::<Vec<_>>().join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check the `[workspace.lints]` table is inherited by every package and lists every pedantic
+/// clippy lint.
+///
+/// Pedantic lints are allow-by-default, so the rust-bitcoin convention is to list each of them
+/// in `[workspace.lints.clippy]` with an explicit level. Clippy adds, renames, and removes lints
+/// over time, so the table is compared against the lints of the pinned nightly. Skipped for
+/// workspaces without a `[workspace.lints]` table.
+fn check_workspace_lints(
+    sh: &Shell,
+    packages: &[Package],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(get_workspace_root(sh)?.join("Cargo.toml"))?;
+    let root: toml::Value = toml::from_str(&contents)?;
+    let Some(workspace_lints) = root.get("workspace").and_then(|w| w.get("lints")) else {
+        return Ok(());
+    };
+
+    rbmt_eprintln!("Checking workspace lints...");
+
+    let mut missing_packages = Vec::new();
+    for package in packages {
+        if !Manifest::read(&package.dir)?.workspace_lints {
+            missing_packages.push(package.name.clone());
+        }
+    }
+    if !missing_packages.is_empty() {
+        return Err(Box::new(LintError::MissingWorkspaceLints(missing_packages)));
+    }
+
+    if let Some(table) = workspace_lints.get("clippy").and_then(toml::Value::as_table) {
+        let help = rbmt_cmd!(sh, "clippy-driver -W help").read()?;
+        let configured: BTreeSet<String> =
+            table.keys().map(|lint| lint.replace('-', "_")).collect();
+        let (missing, unknown) = ClippyLints::parse(&help).compare(&configured);
+        if !missing.is_empty() || !unknown.is_empty() {
+            return Err(Box::new(LintError::OutdatedClippyLints { missing, unknown }));
+        }
+    }
+
+    rbmt_eprintln!("Workspace lints are inherited and up to date");
+    Ok(())
+}
+
+/// The lints of a clippy version, as listed by `clippy-driver -W help`.
+#[derive(Debug, Default)]
+struct ClippyLints {
+    /// Every lint and lint group, without the `clippy::` prefix and with underscores.
+    known: BTreeSet<String>,
+    /// Lints of the pedantic group.
+    pedantic: BTreeSet<String>,
+}
+
+impl ClippyLints {
+    /// Parse the lint tables of `-W help`.
+    ///
+    /// A lint is listed as `clippy::name  level  meaning` and a group as
+    /// `clippy::group  clippy::lint, clippy::lint, ...`, both with hyphens in the names.
+    fn parse(help: &str) -> Self {
+        fn name(lint: &str) -> Option<String> {
+            lint.trim_end_matches(',').strip_prefix("clippy::").map(|name| name.replace('-', "_"))
+        }
+
+        let mut lints = Self::default();
+        for line in help.lines() {
+            let mut columns = line.split_whitespace();
+            let (Some(first), Some(second)) = (columns.next(), columns.next()) else {
+                continue;
+            };
+            let Some(lint) = name(first) else {
+                continue;
+            };
+            if second.starts_with("clippy::") && lint == "pedantic" {
+                lints.pedantic = std::iter::once(second).chain(columns).filter_map(name).collect();
+            }
+            if matches!(second, "allow" | "warn" | "deny" | "forbid")
+                || second.starts_with("clippy::")
+            {
+                lints.known.insert(lint);
+            }
+        }
+        lints
+    }
+
+    /// Pedantic lints missing from the configured ones, and configured lints which do not
+    /// exist. Missing lints are not reported if the whole pedantic group is configured.
+    fn compare(&self, configured: &BTreeSet<String>) -> (Vec<String>, Vec<String>) {
+        let missing = if configured.contains("pedantic") {
+            Vec::new()
+        } else {
+            self.pedantic.difference(configured).cloned().collect()
+        };
+        let unknown = configured.difference(&self.known).cloned().collect();
+        (missing, unknown)
+    }
+}
+
+/// Check for dependencies which no target of a package uses.
+///
+/// The package is checked with no, default, and all features, or every feature set of its test
+/// matrix with `unused_dependencies_matrix`, using clippy and the `unused_crate_dependencies`
+/// lint, which each target reports for the dependencies it was given but does not use. A dependency is used if any target which can use it, in any feature
+/// set which enables it, does not report it: normal dependencies are given to every target
+/// except the build script, dev-dependencies to tests, benches, and examples, and
+/// build-dependencies to the build script.
+///
+/// Dependencies only used behind a `cfg` the matrix does not build, e.g. a target OS, are
+/// reported too and can be allowed with `allowed_unused_dependencies`. Under `--plan` the clippy
+/// runs are recorded and nothing is reported.
+fn check_unused_deps(sh: &Shell, packages: &[Package]) -> Result<(), Box<dyn std::error::Error>> {
+    rbmt_eprintln!("Checking for unused dependencies...");
+
+    let mut unused_deps = Vec::new();
+    let mut stale_entries = Vec::new();
+
+    for package in packages {
+        let config = LintConfig::load(&package.dir)?;
+        let _old_dir = sh.push_dir(&package.dir);
+
+        let feature_sets = if config.unused_dependencies_matrix {
+            test::feature_matrix(package, git::current_commit_id(sh))?
+        } else {
+            representative_feature_sets(package)
+        };
+        let mut unused = UnusedDependencies::default();
+        for features in feature_sets {
+            let args = test::feature_args(features.as_deref());
+            rbmt_eprintln!("Checking dependencies of {} with {}", package.name, args.join(" "));
+            let clippy = cargo_cmd(sh)
+                .arg("clippy")
+                .arg("--all-targets")
+                .arg("--message-format=json")
+                .args(&args)
+                .args(["--", "-W", "unused-crate-dependencies"]);
+            if plan::is_active() {
+                clippy.run_with_capture()?;
+                continue;
+            }
+            let metadata =
+                cargo_cmd(sh).arg("metadata").arg("--format-version=1").args(&args).read()?;
+            let dependencies = EnabledDependency::parse(&metadata, &package.id)?;
+            let messages = clippy.read()?;
+            unused.record(&dependencies, &CheckedTargets::parse(&messages, &package.id));
+        }
+        if plan::is_active() {
+            continue;
+        }
+
+        let (found, stale) = unused.report(&config.allowed_unused_dependencies);
+        if !found.is_empty() {
+            unused_deps.push((package.name.clone(), found));
+        }
+        if !stale.is_empty() {
+            stale_entries.push((package.name.clone(), stale));
+        }
+    }
+
+    if !unused_deps.is_empty() {
+        return Err(Box::new(LintError::UnusedDependencies(unused_deps)));
+    }
+    if !stale_entries.is_empty() {
+        return Err(Box::new(LintError::StaleAllowedUnusedDependencies(stale_entries)));
+    }
+
+    rbmt_eprintln!("No unused dependencies found");
+    Ok(())
+}
+
+/// Feature selections which enable every dependency some way: no, default, and all features.
+///
+/// Selections enabling the same features as an earlier one are dropped, see
+/// [`test::feature_matrix`] for the selection format.
+fn representative_feature_sets(package: &Package) -> Vec<Option<Vec<String>>> {
+    let mut sets = vec![Some(Vec::new()), None];
+    if package.feature_graph.contains_key("default") {
+        sets.insert(1, Some(vec!["default".to_owned()]));
+    }
+    let mut seen = BTreeSet::new();
+    sets.retain(|set| seen.insert(test::resolve_features(&package.feature_graph, set.as_deref())));
+    sets
+}
+
+/// Kind of a dependency, deciding which targets are given it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DependencyKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+impl std::fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Normal => write!(f, "[dependencies]"),
+            Self::Dev => write!(f, "[dev-dependencies]"),
+            Self::Build => write!(f, "[build-dependencies]"),
+        }
+    }
+}
+
+/// A dependency enabled with some features, from the resolve graph of `cargo metadata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EnabledDependency {
+    /// Package name of the dependency.
+    name: String,
+    /// Name of the crate in code, as reported by the lint.
+    crate_name: String,
+    kind: DependencyKind,
+}
+
+impl EnabledDependency {
+    /// The dependencies of the package with the features `cargo metadata` was given, once per
+    /// kind.
+    fn parse(metadata: &str, package_id: &str) -> Result<Vec<Self>, Box<dyn std::error::Error>> {
+        let metadata: serde_json::Value = serde_json::from_str(metadata)?;
+        let names: HashMap<&str, &str> = metadata["packages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| Some((p["id"].as_str()?, p["name"].as_str()?)))
+            .collect();
+        let node = metadata["resolve"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|node| node["id"] == package_id)
+            .ok_or_else(|| format!("Package {} not found in cargo metadata", package_id))?;
+
+        let mut dependencies = Vec::new();
+        for dep in node["deps"].as_array().into_iter().flatten() {
+            let (Some(crate_name), Some(pkg)) = (dep["name"].as_str(), dep["pkg"].as_str()) else {
+                continue;
+            };
+            let name = names.get(pkg).copied().unwrap_or(crate_name);
+            for dep_kind in dep["dep_kinds"].as_array().into_iter().flatten() {
+                let kind = match dep_kind["kind"].as_str() {
+                    None => DependencyKind::Normal,
+                    Some("dev") => DependencyKind::Dev,
+                    Some("build") => DependencyKind::Build,
+                    Some(_) => continue,
+                };
+                let dependency =
+                    Self { name: name.to_owned(), crate_name: crate_name.to_owned(), kind };
+                if !dependencies.contains(&dependency) {
+                    dependencies.push(dependency);
+                }
+            }
+        }
+        Ok(dependencies)
+    }
+}
+
+/// Targets of a package checked by clippy, and the crates each one reported unused.
+///
+/// Targets are identified by their kind and name, so a library and its unit tests share one.
+#[derive(Debug, Default)]
+struct CheckedTargets {
+    /// Whether each target was built as a test, which gives it the dev-dependencies.
+    targets: BTreeMap<(String, String), bool>,
+    unused: BTreeMap<(String, String), BTreeSet<String>>,
+}
+
+impl CheckedTargets {
+    /// Parse the JSON messages of `cargo clippy --message-format=json` for the package.
+    fn parse(messages: &str, package_id: &str) -> Self {
+        let mut checked = Self::default();
+        for message in
+            messages.lines().filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        {
+            if message["package_id"] != package_id {
+                continue;
+            }
+            let (Some(kind), Some(name)) =
+                (message["target"]["kind"][0].as_str(), message["target"]["name"].as_str())
+            else {
+                continue;
+            };
+            let target = (kind.to_owned(), name.to_owned());
+            if message["reason"] == "compiler-artifact" {
+                *checked.targets.entry(target).or_default() |= message["profile"]["test"] == true;
+            } else if message["reason"] == "compiler-message"
+                && message["message"]["code"]["code"] == "unused_crate_dependencies"
+            {
+                // "extern crate `name` is unused in crate `target`"
+                let text = message["message"]["message"].as_str().unwrap_or_default();
+                if let Some(crate_name) = text.split('`').nth(1) {
+                    checked.unused.entry(target).or_default().insert(crate_name.to_owned());
+                }
+            }
+        }
+        checked
+    }
+
+    /// Whether a target is given dependencies of the kind.
+    fn is_given(target: &(String, String), test: bool, kind: DependencyKind) -> bool {
+        let build_script = target.0 == "custom-build";
+        match kind {
+            DependencyKind::Normal => !build_script,
+            DependencyKind::Dev =>
+                test || matches!(target.0.as_str(), "test" | "bench" | "example"),
+            DependencyKind::Build => build_script,
+        }
+    }
+
+    /// Whether any target given the dependency uses it, `None` if no target was given it.
+    fn uses(&self, dependency: &EnabledDependency) -> Option<bool> {
+        let mut given = self
+            .targets
+            .iter()
+            .filter(|(target, &test)| Self::is_given(target, test, dependency.kind))
+            .peekable();
+        given.peek()?;
+        Some(given.any(|(target, _)| {
+            !self.unused.get(target).is_some_and(|unused| unused.contains(&dependency.crate_name))
+        }))
+    }
+}
+
+/// Dependencies found unused and used across the feature sets of a package.
+#[derive(Debug, Default)]
+struct UnusedDependencies {
+    unused: BTreeSet<(DependencyKind, String)>,
+    used: BTreeSet<(DependencyKind, String)>,
+}
+
+impl UnusedDependencies {
+    /// Record the dependencies enabled for a feature set and the targets checked with it.
+    fn record(&mut self, dependencies: &[EnabledDependency], targets: &CheckedTargets) {
+        for dependency in dependencies {
+            let key = (dependency.kind, dependency.name.clone());
+            match targets.uses(dependency) {
+                Some(true) => {
+                    self.used.insert(key);
+                }
+                Some(false) => {
+                    self.unused.insert(key);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Dependencies unused in every feature set which are not allowed, e.g.
+    /// `[dev-dependencies] serde`, and allowed entries which are not unused.
+    fn report(&self, allowed: &[String]) -> (Vec<String>, Vec<String>) {
+        let unused: Vec<&(DependencyKind, String)> = self.unused.difference(&self.used).collect();
+        let found = unused
+            .iter()
+            .filter(|(_, name)| !allowed.contains(name))
+            .map(|(kind, name)| format!("{} {}", kind, name))
+            .collect();
+        let stale = allowed
+            .iter()
+            .filter(|entry| !unused.iter().any(|(_, name)| name == *entry))
+            .cloned()
+            .collect();
+        (found, stale)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -712,6 +1544,45 @@ mod tests {
         assert!(dupes.contains_key("hex-conservative"), "non-allowed duplicate should be reported");
     }
 
+    #[test]
+    fn allowed_cross_package_duplicates_not_reported() {
+        let output = "\
+0bitcoin_hashes v0.13.0
+1pkg1 v0.1.0 (/path/to/pkg1)
+
+0bitcoin_hashes v0.14.1
+1pkg2 v0.1.0 (/path/to/pkg2)
+
+0hex-conservative v0.1.2
+1pkg1 v0.1.0 (/path/to/pkg1)
+
+0hex-conservative v0.2.2
+1pkg2 v0.1.0 (/path/to/pkg2)
+";
+        let allowed = vec!["bitcoin_hashes".to_string(), "secp256k1".to_string()];
+        let tree = DuplicateTree::parse(output, &allowed);
+        let dupes = tree.cross_package_duplicates();
+        assert_eq!(dupes.keys().copied().collect::<Vec<_>>(), ["hex-conservative"]);
+        assert_eq!(stale_allowed_cross_package_duplicates(output, &allowed), ["secp256k1"]);
+    }
+
+    #[test]
+    fn allowed_cross_package_duplicate_within_one_package_is_stale() {
+        // Both versions of bitcoin_hashes are pulled in by pkg1 alone, which the per-package
+        // check covers, so the workspace allowlist entry is no longer needed.
+        let output = "\
+0bitcoin_hashes v0.13.0
+1pkg1 v0.1.0 (/path/to/pkg1)
+
+0bitcoin_hashes v0.14.1
+1pkg1 v0.1.0 (/path/to/pkg1)
+2pkg2 v0.1.0 (/path/to/pkg2)
+";
+        let allowed = vec!["bitcoin_hashes".to_string()];
+        assert!(DuplicateTree::parse(output, &allowed).cross_package_duplicates().is_empty());
+        assert_eq!(stale_allowed_cross_package_duplicates(output, &allowed), ["bitcoin_hashes"]);
+    }
+
     #[test]
     fn stale_allowed_duplicates_reported() {
         let output = "\
@@ -793,4 +1664,294 @@ mod tests {
         assert!(tree.duplicates().contains_key("hex_conservative"));
         assert!(tree.semver_trick_packages().contains(&"my_crate".to_string()));
     }
+
+    #[test]
+    fn clippy_lints_compared_to_table() {
+        let help = "\
+Lint checks loaded by this crate:
+
+                                   name  default  meaning
+                                   ----  -------  -------
+                clippy::assigning-clones  allow    assigning the result of cloning may be inefficient
+                      clippy::doc-markdown  allow    detects syntax that could be a link
+                   clippy::needless-return  warn     using a return statement like `return expr;`
+
+Lint groups loaded by this crate:
+
+                                   name  sub-lints
+                                   ----  ---------
+                          clippy::all  clippy::needless-return
+                     clippy::pedantic  clippy::assigning-clones, clippy::doc-markdown
+";
+        let lints = ClippyLints::parse(help);
+        assert_eq!(lints.pedantic, ["assigning_clones", "doc_markdown"].map(String::from).into());
+
+        let configured: BTreeSet<String> =
+            ["doc_markdown", "needless_return", "removed_lint"].map(String::from).into();
+        let (missing, unknown) = lints.compare(&configured);
+        assert_eq!(missing, ["assigning_clones"]);
+        assert_eq!(unknown, ["removed_lint"]);
+
+        let (missing, unknown) = lints.compare(&["pedantic".to_owned()].into());
+        assert!(missing.is_empty() && unknown.is_empty());
+    }
+
+    #[test]
+    fn unused_dependencies_across_targets_and_feature_sets() {
+        let id = "path+file:///ws/p#0.1.0";
+        let artifact = |kind: &str, name: &str, test: bool| {
+            format!(
+                r#"{{"reason":"compiler-artifact","package_id":"{}","target":{{"kind":["{}"],"name":"{}"}},"profile":{{"test":{}}}}}"#,
+                id, kind, name, test
+            )
+        };
+        let unused = |kind: &str, name: &str, krate: &str| {
+            format!(
+                r#"{{"reason":"compiler-message","package_id":"{}","target":{{"kind":["{}"],"name":"{}"}},"message":{{"code":{{"code":"unused_crate_dependencies"}},"message":"extern crate `{}` is unused in crate `{}`"}}}}"#,
+                id, kind, name, krate, name
+            )
+        };
+        let messages = [
+            unused("custom-build", "build-script-build", "cc"),
+            artifact("custom-build", "build-script-build", false),
+            unused("lib", "p", "unused_dep"),
+            unused("lib", "p", "optional_dep"),
+            artifact("lib", "p", false),
+            // Integration tests use the dev-dependency, but none of the normal ones.
+            unused("test", "t", "unused_dep"),
+            unused("test", "t", "used_dep"),
+            unused("test", "t", "optional_dep"),
+            unused("test", "t", "unused_dev"),
+            artifact("test", "t", true),
+            unused("lib", "p", "proptest"),
+            unused("lib", "p", "unused_dev"),
+            artifact("lib", "p", true),
+        ]
+        .join("\n");
+        let targets = CheckedTargets::parse(&messages, id);
+
+        let dep = |name: &str, kind| EnabledDependency {
+            name: name.replace('_', "-"),
+            crate_name: name.to_owned(),
+            kind,
+        };
+        let all_features = [
+            dep("used_dep", DependencyKind::Normal),
+            dep("unused_dep", DependencyKind::Normal),
+            dep("optional_dep", DependencyKind::Normal),
+            dep("proptest", DependencyKind::Dev),
+            dep("unused_dev", DependencyKind::Dev),
+            dep("cc", DependencyKind::Build),
+        ];
+        let mut unused_deps = UnusedDependencies::default();
+        unused_deps.record(&all_features, &targets);
+        let (found, stale) = unused_deps.report(&["optional-dep".to_owned(), "gone".to_owned()]);
+        assert_eq!(
+            found,
+            [
+                "[dependencies] unused-dep",
+                "[dev-dependencies] unused-dev",
+                "[build-dependencies] cc"
+            ]
+        );
+        assert_eq!(stale, ["gone"]);
+
+        // A feature set in which the optional dependency is used.
+        let targets = CheckedTargets::parse(&artifact("lib", "p", false), id);
+        unused_deps.record(&[dep("optional_dep", DependencyKind::Normal)], &targets);
+        let (_, stale) = unused_deps.report(&["optional-dep".to_owned()]);
+        assert_eq!(stale, ["optional-dep"]);
+    }
+
+    #[test]
+    fn representative_feature_sets_are_distinct() {
+        let package = |features: &[(&str, &[&str])]| {
+            let feature_graph: BTreeMap<String, Vec<String>> = features
+                .iter()
+                .map(|(f, enables)| {
+                    ((*f).to_owned(), enables.iter().map(|&e| e.to_owned()).collect())
+                })
+                .collect();
+            Package {
+                name: "p".to_owned(),
+                dir: "p".into(),
+                id: "p".to_owned(),
+                deps: Vec::new(),
+                dev_deps: Vec::new(),
+                features: feature_graph.keys().filter(|f| *f != "default").cloned().collect(),
+                feature_graph,
+                publish: true,
+            }
+        };
+
+        let default = Some(vec!["default".to_owned()]);
+        assert_eq!(
+            representative_feature_sets(&package(&[
+                ("default", &["std"]),
+                ("std", &[]),
+                ("serde", &[])
+            ])),
+            [Some(Vec::new()), default.clone(), None]
+        );
+        // Default features which enable everything are all features.
+        assert_eq!(
+            representative_feature_sets(&package(&[("default", &["std"]), ("std", &[])])),
+            [Some(Vec::new()), default]
+        );
+        assert_eq!(representative_feature_sets(&package(&[])), [Some(Vec::new())]);
+    }
+
+    #[test]
+    fn feature_problems_found() {
+        let source = r#"
+            #![cfg_attr(not(feature = "std"), no_std)]
+            #[cfg(any(feature="alloc", feature = "serde"))]
+            pub mod features;
+            // Plural keys are not predicates: features = "nope"
+            #[cfg(target_feature = "sse2")]
+            #[cfg_attr(docsrs, doc(cfg(feature = "rand")))]
+        "#;
+        assert_eq!(cfg_features(source), ["std", "alloc", "serde", "rand"]);
+
+        let graph: BTreeMap<String, Vec<String>> = [
+            ("default", vec!["std"]),
+            ("std", vec!["hashes/std"]),
+            ("alloc", vec![]),
+            ("serde", vec!["dep:serde"]),
+            ("rand", vec!["dep:rand"]),
+            ("forward", vec!["hashes/serde"]),
+            ("unused", vec![]),
+            ("alias", vec!["unused"]),
+            ("secp256k1", vec!["dep:secp256k1"]),
+        ]
+        .into_iter()
+        .map(|(feature, enables)| {
+            (feature.to_owned(), enables.into_iter().map(str::to_owned).collect())
+        })
+        .collect();
+        let declared: Vec<String> =
+            ["default", "std", "alloc", "serde", "rand", "forward", "unused", "alias"]
+                .map(str::to_owned)
+                .into();
+        let referenced: BTreeSet<String> = ["std", "alloc"].map(str::to_owned).into();
+        let configured = test::TestConfig::parse(
+            r#"
+            [package.metadata.rbmt.test]
+            exact_features = [["std", "gone", "hashes/std"]]
+            msrv_overrides = { "rand" = "1.70.0" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            feature_problems(&graph, &declared, &referenced, &configured),
+            [
+                "feature `alias` is never referenced by a `cfg` or another feature",
+                "optional dependency `secp256k1` is exposed as an implicit feature, enable it with `dep:secp256k1`",
+                "feature `std` does not enable `alloc`",
+                "`exact_features` names feature `gone` which does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_problems_found() {
+        let member =
+            |name: &str, publish: bool, api_snapshot: bool, manifest: &str| ManifestMember {
+                name: name.to_owned(),
+                publish,
+                api_snapshot,
+                manifest: Manifest::parse(manifest).unwrap(),
+            };
+        let members = [
+            member(
+                "bitcoin",
+                true,
+                true,
+                r#"
+                [package]
+                name = "bitcoin"
+                license = "CC0-1.0"
+                repository = "https://github.com/rust-bitcoin/rust-bitcoin"
+                description = "General purpose library for using and interoperating with Bitcoin."
+                edition.workspace = true
+                rust-version = "1.63.0"
+                exclude = ["tests", "api-test-cases"]
+
+                [dependencies]
+                hashes = { package = "bitcoin_hashes", version = "0.16.0" }
+                serde = { version = "1.0.103", optional = true }
+
+                [target.'cfg(fuzzing)'.dev-dependencies]
+                honggfuzz = "0.5"
+                "#,
+            ),
+            member(
+                "hashes",
+                true,
+                true,
+                r#"
+                [package]
+                name = "bitcoin_hashes"
+                license-file = "LICENSE"
+                edition = "2018"
+                rust-version.workspace = true
+                exclude = ["/api/**"]
+
+                [dependencies]
+                serde = { version = "1.0", default-features = false, optional = true }
+                hex = { package = "hex-conservative", version = "^0.2" }
+                "#,
+            ),
+            member(
+                "fuzz",
+                false,
+                false,
+                r#"
+                [package]
+                name = "fuzz"
+                edition = "2021"
+
+                [dependencies]
+                bitcoin = { path = "../bitcoin" }
+                honggfuzz = { version = "0.5.56", default-features = false }
+                serde = { version = "1.0.103" }
+                hex = { package = "hex-conservative", version = "0.2.0" }
+                "#,
+            ),
+        ];
+        let workspace_package = toml::from_str(
+            r#"
+            edition = "2021"
+            rust-version = "1.63.0"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest_problems(
+                &members,
+                &workspace_package,
+                &["honggfuzz".to_owned(), "hex-conservative".to_owned()]
+            ),
+            [
+                "bitcoin: `exclude` does not cover the `api/` snapshots",
+                "hashes: missing `repository`, `description`",
+                "`edition` differs across packages: 2018 (hashes), 2021 (bitcoin, fuzz)",
+                "dependency `serde` has different version requirements: 1.0.0 (hashes), 1.0.103 (bitcoin, fuzz)",
+                "stale entry `hex-conservative` in `allowed_requirement_mismatches`, every package declares the same requirement",
+            ]
+        );
+    }
+
+    #[test]
+    fn requirements_are_normalized() {
+        assert_eq!(normalize_requirement("1.0"), "1.0.0");
+        assert_eq!(normalize_requirement(" ^1 "), "1.0.0");
+        assert_eq!(normalize_requirement("0.32.5"), "0.32.5");
+        assert_eq!(normalize_requirement("0.0"), "0.0");
+        assert_eq!(normalize_requirement("~1.0"), "~1.0");
+        assert_eq!(normalize_requirement(">=1.0, <2.0"), ">=1.0, <2.0");
+        assert_eq!(normalize_requirement("1.0.0-rc.1"), "1.0.0-rc.1");
+    }
 }
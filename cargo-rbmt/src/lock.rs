@@ -74,7 +74,7 @@ impl Drop for LockFileGuard {
 }
 
 /// Represents the different types of managed lockfiles.
-#[derive(Debug, Clone, Copy, ValueEnum, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
 pub enum LockFile {
     /// Minimal (oldest) dependency versions that satisfy dependency constraints.
     Minimal,
@@ -143,11 +143,11 @@ impl LockFile {
 
                 fs::copy(&source, &dest).map_err(|e| -> Box<dyn std::error::Error> {
                     format!(
-                        "Failed to restore {} lockfile (workspace: {:?}, from: {:?}, to: {:?}): {}",
+                        "Failed to restore {} lockfile (workspace: {}, from: {}, to: {}): {}",
                         self.filename(),
-                        workspace_root,
-                        source,
-                        dest,
+                        workspace_root.display(),
+                        source.display(),
+                        dest.display(),
                         e
                     )
                     .into()
@@ -5,17 +5,27 @@
 mod environment;
 
 mod api;
+mod bench;
+mod bisect;
 mod cleanup;
+mod combination;
+mod coverage;
 mod docs;
 mod fmt;
 mod generate;
 mod git;
 mod integration;
+mod journal;
 mod lint;
 mod lock;
+mod miri;
+mod msrv;
+mod plan;
 mod prerelease;
+mod report;
 mod run;
 mod semantic_version;
+mod shard;
 mod test;
 mod toolchain;
 mod toolchains;
@@ -23,9 +33,11 @@ mod tools;
 mod tree;
 mod version;
 
+use std::num::NonZeroUsize;
 use std::process;
 
 use clap::{Parser, Subcommand};
+use combination::{Combination, Selection};
 use lock::{GeneratableLockFile, LockFile};
 use toolchain::Toolchain;
 use xshell::Shell;
@@ -42,6 +54,18 @@ struct Cli {
     #[arg(short = 'i', long, global = true)]
     ignore_version: bool,
 
+    /// Print the cargo commands of `test`, `lint`, `docs`, or `api` instead of running them.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "text"
+    )]
+    plan: Option<plan::PlanFormat>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -84,21 +108,112 @@ enum Commands {
         #[arg(long)]
         open: bool,
     },
-    /// Run tests with specified toolchain.
-    Test {
+    /// Check for undefined behavior by running tests under Miri with the nightly toolchain.
+    Miri {
         /// Lockfile to use for dependencies.
         #[arg(long = "lockfile", alias = "lock-file", value_enum, default_value_t = LockFile::Recent)]
         lockfile: LockFile,
-        /// Toolchain to use: stable, nightly, or msrv.
-        #[arg(long, value_enum, default_value_t = Toolchain::Stable)]
-        toolchain: Toolchain,
+    },
+    /// Check that no dependency reachable with default features requires a newer toolchain than the MSRV.
+    Msrv {
+        /// Lockfiles to audit, comma separated or `all` (default: every lockfile in the workspace).
+        #[arg(
+            long = "lockfile",
+            alias = "lock-file",
+            value_name = "LOCKFILE",
+            value_delimiter = ',',
+            value_parser = combination::parse::<LockFile>
+        )]
+        lockfiles: Vec<Selection<LockFile>>,
+        /// Check the `msrv_overrides` of the test configuration against the ones inferred from dependencies.
+        #[arg(long)]
+        overrides: bool,
+    },
+    /// Run tests with specified toolchain.
+    Test {
+        /// Lockfiles to use for dependencies, comma separated or `all`, e.g. `minimal,maximum`.
+        #[arg(
+            long = "lockfile",
+            alias = "lock-file",
+            value_name = "LOCKFILE",
+            value_delimiter = ',',
+            default_value = "recent",
+            value_parser = combination::parse::<LockFile>
+        )]
+        lockfiles: Vec<Selection<LockFile>>,
+        /// Toolchains to use: stable, nightly, msrv, comma separated or `all`.
+        #[arg(
+            long = "toolchain",
+            value_name = "TOOLCHAIN",
+            value_delimiter = ',',
+            default_value = "stable",
+            value_parser = combination::parse::<Toolchain>
+        )]
+        toolchains: Vec<Selection<Toolchain>>,
         /// Test every commit between the given baseline ref and HEAD to verify bisectability.
         #[arg(long)]
         baseline: Option<String>,
+        /// Only test packages changed since the given ref, plus every package depending on them.
+        #[arg(long, value_name = "REF")]
+        changed_since: Option<String>,
+        /// Number of feature sets to test concurrently, each with its own target directory.
+        #[arg(short, long, default_value_t = NonZeroUsize::MIN)]
+        jobs: NonZeroUsize,
+        /// Skip units which passed in a previous run with the same lockfile and toolchain.
+        #[arg(long)]
+        resume: bool,
+        /// Write a report of every tested unit to the given path.
+        #[arg(long, value_name = "PATH")]
+        report: Option<std::path::PathBuf>,
+        /// Format of the `--report`.
+        #[arg(long, value_enum, default_value_t = report::ReportFormat::Json, requires = "report")]
+        report_format: report::ReportFormat,
+        /// Build and test the packages which enable this sanitizer, requires `--toolchain nightly`.
+        #[arg(long, value_enum)]
+        sanitizer: Option<test::Sanitizer>,
+        /// Re-run failing tests up to N times, marking those which pass on a retry as flaky.
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retries: usize,
+        /// Fail if any test only passed on a retry.
+        #[arg(long, requires = "retries")]
+        strict_flaky: bool,
+        /// Rewrite the expected output files of examples with their current output.
+        #[arg(long, conflicts_with = "baseline")]
+        bless: bool,
+        /// Only test shard I of N of the units, e.g. `--shard 2/4`, to split a run across jobs.
+        #[arg(long, value_name = "I/N")]
+        shard: Option<shard::Shard>,
+        /// JSON report of an earlier run (see `--report`), whose durations balance the shards.
+        #[arg(long, value_name = "PATH", requires = "shard")]
+        shard_durations: Option<std::path::PathBuf>,
         /// Cargo arguments (everything after `--`).
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         cargo_args: Vec<String>,
     },
+    /// Run benchmarks with the nightly toolchain and compare them against a baseline ref.
+    Bench {
+        /// Lockfile to use for dependencies.
+        #[arg(long = "lockfile", alias = "lock-file", value_enum, default_value_t = LockFile::Recent)]
+        lockfile: LockFile,
+        /// Also run the benchmarks at the given git ref and report the change of each one.
+        #[arg(long)]
+        baseline: Option<String>,
+        /// Slowdown in percent which fails a benchmark, overriding `[package.metadata.rbmt.bench]`.
+        #[arg(long, value_name = "PERCENT", requires = "baseline")]
+        threshold: Option<f64>,
+        /// Arguments passed to `cargo bench` (everything after `--`).
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        cargo_args: Vec<String>,
+    },
+    /// Measure code coverage of the feature matrix, merged into lcov and HTML reports.
+    Coverage {
+        /// Lockfile to use for dependencies.
+        #[arg(long = "lockfile", alias = "lock-file", value_enum, default_value_t = LockFile::Recent)]
+        lockfile: LockFile,
+        /// Also measure coverage at the given git ref and report the change.
+        #[arg(long)]
+        baseline: Option<String>,
+    },
     /// Run bitcoin core integration tests.
     Integration,
     /// Update dependency versions in lockfiles.
@@ -109,12 +224,25 @@ enum Commands {
     },
     /// Run arbitrary cargo commands with toolchain and lockfile management.
     Run {
-        /// Lockfile to use for dependencies.
-        #[arg(long = "lockfile", alias = "lock-file", value_enum, default_value_t = LockFile::Recent)]
-        lockfile: LockFile,
-        /// Toolchain to use: stable, nightly, or msrv.
-        #[arg(long, value_enum, default_value_t = Toolchain::Stable)]
-        toolchain: Toolchain,
+        /// Lockfiles to use for dependencies, comma separated or `all`, e.g. `minimal,maximum`.
+        #[arg(
+            long = "lockfile",
+            alias = "lock-file",
+            value_name = "LOCKFILE",
+            value_delimiter = ',',
+            default_value = "recent",
+            value_parser = combination::parse::<LockFile>
+        )]
+        lockfiles: Vec<Selection<LockFile>>,
+        /// Toolchains to use: stable, nightly, msrv, comma separated or `all`.
+        #[arg(
+            long = "toolchain",
+            value_name = "TOOLCHAIN",
+            value_delimiter = ',',
+            default_value = "stable",
+            value_parser = combination::parse::<Toolchain>
+        )]
+        toolchains: Vec<Selection<Toolchain>>,
         /// Run the command on every commit between the given baseline ref and HEAD to ensure consistency.
         #[arg(long)]
         baseline: Option<String>,
@@ -122,6 +250,21 @@ enum Commands {
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
+    /// Find the first commit since a baseline ref where a command fails, by binary search.
+    Bisect {
+        /// Lockfile to activate at each commit.
+        #[arg(long = "lockfile", alias = "lock-file", value_enum, default_value_t = LockFile::Recent)]
+        lockfile: LockFile,
+        /// Toolchain for a cargo command: stable, nightly, or msrv.
+        #[arg(long, value_enum, default_value_t = Toolchain::Stable)]
+        toolchain: Toolchain,
+        /// Last known good ref (tag, branch, or commit).
+        #[arg(long)]
+        baseline: String,
+        /// Command to run at each commit (everything after `--`), `cargo ...` or an rbmt subcommand.
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
     /// Run pre-release readiness checks.
     Prerelease {
         /// Run checks even for packages that have pre-release checks disabled.
@@ -190,81 +333,127 @@ fn main() {
         }
     }
 
-    match cli.command {
-        Commands::Version => println!("{}", env!("RBMT_BUILD_VERSION")),
-        Commands::Api { lockfile, baseline, snapshot } => {
-            if let Err(e) = api::run(&sh, lockfile, &cli.packages, baseline.as_deref(), snapshot) {
-                eprintln!("Error running API check: {}", e);
-                process::exit(1);
-            }
+    if cli.plan.is_some() {
+        if !matches!(
+            cli.command,
+            Commands::Test { .. }
+                | Commands::Lint { .. }
+                | Commands::Docs { .. }
+                | Commands::Api { .. }
+        ) {
+            eprintln!("Error: --plan is only supported by the test, lint, docs, and api commands");
+            process::exit(1);
+        }
+        // Planning must leave the working tree alone, so it cannot check out other commits.
+        if matches!(
+            cli.command,
+            Commands::Test { baseline: Some(_), .. } | Commands::Api { baseline: Some(_), .. }
+        ) {
+            eprintln!("Error: --plan does not support --baseline, which checks out other commits");
+            process::exit(1);
+        }
+        plan::start();
+    }
+
+    if let Err(e) = dispatch(&sh, cli.command, &cli.packages) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    if let Some(format) = cli.plan {
+        let root = environment::get_workspace_root(&sh).unwrap_or_else(|_| sh.current_dir());
+        plan::print(&root, format);
+    }
+}
+
+/// Run a command, returning an error message describing its failure.
+fn dispatch(sh: &Shell, command: Commands, packages: &[String]) -> Result<(), String> {
+    match command {
+        Commands::Version => {
+            println!("{}", env!("RBMT_BUILD_VERSION"));
+            Ok(())
         }
+        Commands::Api { lockfile, baseline, snapshot } =>
+            api::run(sh, lockfile, packages, baseline.as_deref(), snapshot)
+                .map_err(|e| format!("Error running API check: {}", e)),
         Commands::Fmt { check } =>
-            if let Err(e) = fmt::run(&sh, check, &cli.packages) {
-                eprintln!("Error running fmt task: {}", e);
-                process::exit(1);
-            },
+            fmt::run(sh, check, packages).map_err(|e| format!("Error running fmt task: {}", e)),
         Commands::Lint { lockfile } =>
-            if let Err(e) = lint::run(&sh, lockfile, &cli.packages) {
-                eprintln!("Error running lint task: {}", e);
-                process::exit(1);
-            },
+            lint::run(sh, lockfile, packages).map_err(|e| format!("Error running lint task: {}", e)),
         Commands::Docs { lockfile, no_docsrs, open } => {
             let mode = if no_docsrs { docs::DocsMode::Docs } else { docs::DocsMode::DocsRs };
-            if let Err(e) = docs::run(&sh, lockfile, &cli.packages, mode, open) {
-                eprintln!("Error building docs: {}", e);
-                process::exit(1);
-            }
+            docs::run(sh, lockfile, packages, mode, open)
+                .map_err(|e| format!("Error building docs: {}", e))
         }
-        Commands::Test { lockfile, toolchain, baseline, cargo_args } =>
-            if let Err(e) =
-                test::run(&sh, lockfile, toolchain, baseline.as_deref(), &cli.packages, &cargo_args)
-            {
-                eprintln!("Error running tests: {}", e);
-                process::exit(1);
-            },
-        Commands::Integration =>
-            if let Err(e) = integration::run(&sh, &cli.packages) {
-                eprintln!("Error running integration tests: {}", e);
-                process::exit(1);
-            },
+        Commands::Miri { lockfile } =>
+            miri::run(sh, lockfile, packages).map_err(|e| format!("Error running miri: {}", e)),
+        Commands::Msrv { lockfiles, overrides } => {
+            let lockfiles = combination::lockfiles(&lockfiles);
+            msrv::run(sh, packages, &lockfiles, overrides)
+                .map_err(|e| format!("Error auditing dependency MSRVs: {}", e))
+        }
+        Commands::Test {
+            lockfiles,
+            toolchains,
+            baseline,
+            changed_since,
+            jobs,
+            resume,
+            report,
+            report_format,
+            sanitizer,
+            retries,
+            strict_flaky,
+            bless,
+            shard,
+            shard_durations,
+            cargo_args,
+        } => {
+            let options = test::TestOptions {
+                combinations: Combination::all_of(&lockfiles, &toolchains),
+                baseline,
+                changed_since,
+                jobs,
+                resume,
+                report: report.map(|path| report::ReportTarget { format: report_format, path }),
+                sanitizer,
+                retries,
+                strict_flaky,
+                bless,
+                shard,
+                shard_durations,
+                cargo_args,
+            };
+            test::run(sh, packages, &options).map_err(|e| format!("Error running tests: {}", e))
+        }
+        Commands::Bench { lockfile, baseline, threshold, cargo_args } =>
+            bench::run(sh, lockfile, packages, baseline.as_deref(), threshold, &cargo_args)
+                .map_err(|e| format!("Error running benchmarks: {}", e)),
+        Commands::Coverage { lockfile, baseline } =>
+            coverage::run(sh, lockfile, packages, baseline.as_deref())
+                .map_err(|e| format!("Error measuring coverage: {}", e)),
+        Commands::Integration => integration::run(sh, packages)
+            .map_err(|e| format!("Error running integration tests: {}", e)),
         Commands::Lock { lockfiles } =>
-            if let Err(e) = lock::run(&sh, &lockfiles) {
-                eprintln!("Error updating lockfiles: {}", e);
-                process::exit(1);
-            },
-        Commands::Run { lockfile, toolchain, baseline, args } =>
-            if let Err(e) =
-                run::run(&sh, lockfile, toolchain, baseline.as_deref(), &cli.packages, &args)
-            {
-                eprintln!("Error running cargo command: {}", e);
-                process::exit(1);
-            },
-        Commands::Prerelease { force, baseline } =>
-            if let Err(e) = prerelease::run(&sh, &cli.packages, force, &baseline) {
-                eprintln!("Error running pre-release checks: {}", e);
-                process::exit(1);
-            },
+            lock::run(sh, &lockfiles).map_err(|e| format!("Error updating lockfiles: {}", e)),
+        Commands::Run { lockfiles, toolchains, baseline, args } => {
+            let combinations = Combination::all_of(&lockfiles, &toolchains);
+            run::run(sh, &combinations, baseline.as_deref(), packages, &args)
+                .map_err(|e| format!("Error running cargo command: {}", e))
+        }
+        Commands::Bisect { lockfile, toolchain, baseline, args } =>
+            bisect::run(sh, lockfile, toolchain, &baseline, packages, &args)
+                .map_err(|e| format!("Error bisecting: {}", e)),
+        Commands::Prerelease { force, baseline } => prerelease::run(sh, packages, force, &baseline)
+            .map_err(|e| format!("Error running pre-release checks: {}", e)),
         Commands::Toolchains { update_nightly, update_stable, msrv, nightly, stable, force } =>
-            if let Err(e) =
-                toolchains::run(&sh, update_nightly, update_stable, msrv, nightly, stable, force)
-            {
-                eprintln!("Error setting up toolchains: {}", e);
-                process::exit(1);
-            },
+            toolchains::run(sh, update_nightly, update_stable, msrv, nightly, stable, force)
+                .map_err(|e| format!("Error setting up toolchains: {}", e)),
         Commands::Tools { update, tools } =>
-            if let Err(e) = tools::run(&sh, update, &tools) {
-                eprintln!("Error managing tools: {}", e);
-                process::exit(1);
-            },
+            tools::run(sh, update, &tools).map_err(|e| format!("Error managing tools: {}", e)),
         Commands::Generate =>
-            if let Err(e) = generate::run(&sh, &cli.packages) {
-                eprintln!("Error running file generation: {}", e);
-                process::exit(1);
-            },
-        Commands::Tree { baseline } =>
-            if let Err(e) = tree::run(&sh, &cli.packages, baseline.as_deref()) {
-                eprintln!("Error generating dependency tree: {}", e);
-                process::exit(1);
-            },
+            generate::run(sh, packages).map_err(|e| format!("Error running file generation: {}", e)),
+        Commands::Tree { baseline } => tree::run(sh, packages, baseline.as_deref())
+            .map_err(|e| format!("Error generating dependency tree: {}", e)),
     }
 }
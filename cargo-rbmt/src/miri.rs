@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Undefined behavior checks with Miri on the pinned nightly toolchain.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use xshell::Shell;
+
+use crate::environment::{
+    cargo_cmd, get_workspace_packages, CmdExt, PackageManifest, ProgressGuard,
+};
+use crate::lock::LockFile;
+use crate::toolchain::{add_component, prepare_toolchain, Toolchain};
+
+/// Environment variable read by Miri for its flags.
+const MIRIFLAGS: &str = "MIRIFLAGS";
+
+/// Miri-specific configuration, read from `[package.metadata.rbmt.miri]` in `Cargo.toml`.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct MiriConfig {
+    /// Whether to run Miri on this package (default `false`).
+    ///
+    /// Miri is slow, so packages opt-in, usually the ones with unsafe code.
+    enabled: bool,
+
+    /// Feature sets to test, each built with `--no-default-features`.
+    ///
+    /// If empty, the tests are run once with the default features.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.miri]
+    /// features = [[], ["alloc"], ["std"]]
+    /// ```
+    features: Vec<Vec<String>>,
+
+    /// Flags passed to Miri through `MIRIFLAGS`, after any already set in the environment.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.miri]
+    /// flags = ["-Zmiri-strict-provenance"]
+    /// ```
+    flags: Vec<String>,
+
+    /// Tests to skip, passed to the test harness as `--skip` filters.
+    ///
+    /// Useful for tests which are too slow under Miri or use operations it does not support.
+    skip: Vec<String>,
+}
+
+impl MiriConfig {
+    /// Load Miri configuration from `[package.metadata.rbmt.miri]` in the package's `Cargo.toml`.
+    fn load(crate_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = crate_dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&std::fs::read_to_string(&path)?)
+    }
+
+    /// Parse Miri configuration from the contents of a package's `Cargo.toml`.
+    fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(serde::Deserialize, Default)]
+        struct RbmtTable {
+            #[serde(default)]
+            miri: MiriConfig,
+        }
+
+        Ok(toml::from_str::<PackageManifest<RbmtTable>>(contents)?.package.metadata.rbmt.miri)
+    }
+}
+
+/// Run the tests of every opted-in package under Miri.
+///
+/// # Arguments
+///
+/// * `sh` - The shell context.
+/// * `lockfile` - The lockfile for dependency versions.
+/// * `packages` - Packages to check, empty for all.
+pub fn run(
+    sh: &Shell,
+    lockfile: LockFile,
+    packages: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let packages = get_workspace_packages(sh, packages)?;
+    let _lockfile_guard = lockfile.activate(sh)?;
+    let _progress = ProgressGuard::new();
+    prepare_toolchain(sh, Toolchain::Nightly)?;
+    add_component(sh, "miri")?;
+
+    let mut checked = 0;
+    for package in &packages {
+        let config = MiriConfig::load(&package.dir)?;
+        if !config.enabled {
+            rbmt_eprintln!("Miri is not enabled for {}, skipping", package.name);
+            continue;
+        }
+
+        let _dir = sh.push_dir(&package.dir);
+        let flags = miri_flags(&std::env::var(MIRIFLAGS).unwrap_or_default(), &config.flags);
+        let feature_sets = if config.features.is_empty() {
+            vec![None]
+        } else {
+            config.features.iter().map(|features| Some(features.as_slice())).collect()
+        };
+        for features in feature_sets {
+            match features {
+                None => rbmt_eprintln!("Running Miri on {} with default features", package.name),
+                Some(features) =>
+                    rbmt_eprintln!("Running Miri on {} with features {:?}", package.name, features),
+            }
+            cargo_cmd(sh)
+                .arg("miri")
+                .arg("test")
+                .args(test_args(features, &config.skip))
+                .env(MIRIFLAGS, &flags)
+                .run_with_capture()?;
+        }
+        checked += 1;
+    }
+
+    if checked == 0 {
+        rbmt_eprintln!(
+            "No packages have Miri enabled, opt-in with [package.metadata.rbmt.miri] enabled = true"
+        );
+    } else {
+        rbmt_eprintln!("Miri passed for {} package(s).", checked);
+    }
+    Ok(())
+}
+
+/// `MIRIFLAGS` with the configured flags after any already set in the environment.
+fn miri_flags(existing: &str, flags: &[String]) -> String {
+    existing
+        .split_whitespace()
+        .map(str::to_owned)
+        .chain(flags.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Arguments of `cargo miri test` for a feature set, `None` for the default features, skipping
+/// the given tests.
+fn test_args(features: Option<&[String]>, skip: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(features) = features {
+        args.push("--no-default-features".to_owned());
+        if !features.is_empty() {
+            // Avoid issues with feature names which contain a hyphen.
+            args.push("--features".to_owned());
+            args.push(features.join(","));
+        }
+    }
+    if !skip.is_empty() {
+        args.push("--".to_owned());
+        for test in skip {
+            args.push("--skip".to_owned());
+            args.push(test.clone());
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn miri_config_to_commands() {
+        let parse = |manifest: &str| MiriConfig::parse(manifest).unwrap();
+
+        let config = parse("[package]\nname = \"hashes\"\n");
+        assert!(!config.enabled);
+        assert!(config.features.is_empty() && config.flags.is_empty() && config.skip.is_empty());
+
+        let config = parse(
+            r#"
+            [package]
+            name = "hashes"
+
+            [package.metadata.rbmt.miri]
+            enabled = true
+            features = [[], ["alloc", "serde-std"]]
+            flags = ["-Zmiri-strict-provenance"]
+            skip = ["slow", "ffi"]
+            "#,
+        );
+        assert!(config.enabled);
+        assert_eq!(
+            miri_flags(" -Zmiri-seed=1 ", &config.flags),
+            "-Zmiri-seed=1 -Zmiri-strict-provenance"
+        );
+        assert_eq!(miri_flags("", &config.flags), "-Zmiri-strict-provenance");
+        assert_eq!(test_args(Some(&config.features[0]), &[]), ["--no-default-features"]);
+        assert_eq!(
+            test_args(Some(&config.features[1]), &config.skip),
+            [
+                "--no-default-features",
+                "--features",
+                "alloc,serde-std",
+                "--",
+                "--skip",
+                "slow",
+                "--skip",
+                "ffi"
+            ]
+        );
+        assert!(test_args(None, &[]).is_empty());
+    }
+}
@@ -0,0 +1,638 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Audit the `rust-version` of dependencies against the MSRV of the packages using them.
+//!
+//! A dependency which requires a newer toolchain than a package's `rust-version` breaks builds
+//! with the MSRV, unless it is hidden behind a feature consumers opt in to (see
+//! `docs/gating-msrv.md`). Dependencies are followed from each package with its default
+//! features, through the features which enable them, under every lockfile.
+//!
+//! The same walk infers the MSRV overrides of features which enable dependencies with a higher
+//! `rust-version`, see [`infer_overrides`].
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use serde::Deserialize;
+use xshell::Shell;
+
+use crate::combination::ALL_LOCKFILES;
+use crate::environment::{get_workspace_packages, get_workspace_root, Package, ProgressGuard};
+use crate::lock::LockFile;
+use crate::semantic_version::Version;
+use crate::test::TestConfig;
+
+/// Output of `cargo metadata` with the dependency graph.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+    resolve: Resolve,
+}
+
+impl Metadata {
+    /// Read the metadata of the workspace under the active lockfile.
+    ///
+    /// Every feature is enabled so optional dependencies are resolved, the walk decides which
+    /// ones are built.
+    fn read(sh: &Shell) -> Result<Self, Box<dyn std::error::Error>> {
+        let metadata =
+            rbmt_cmd!(sh, "cargo --locked metadata --all-features --format-version 1").read()?;
+        Ok(serde_json::from_str(&metadata)?)
+    }
+
+    /// The `rust-version` of a package, if it declares a valid one.
+    fn msrv(&self, id: &str) -> Option<Version> {
+        self.packages
+            .iter()
+            .find(|p| p.id == id)
+            .and_then(|p| p.rust_version.as_deref())
+            .and_then(Version::parse)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataPackage {
+    id: String,
+    name: String,
+    version: String,
+    rust_version: Option<String>,
+    dependencies: Vec<Dependency>,
+    /// The `[features]` table, including implicit features of optional dependencies.
+    features: BTreeMap<String, Vec<String>>,
+}
+
+/// A dependency as declared in a manifest.
+#[derive(Debug, Deserialize)]
+struct Dependency {
+    name: String,
+    rename: Option<String>,
+    /// `None` for normal dependencies, else `"dev"` or `"build"`.
+    kind: Option<String>,
+    optional: bool,
+    uses_default_features: bool,
+    features: Vec<String>,
+}
+
+impl Dependency {
+    /// Name the dependency is referred to by in the `[features]` table.
+    fn key(&self) -> &str { self.rename.as_deref().unwrap_or(&self.name) }
+
+    fn is_dev(&self) -> bool { self.kind.as_deref() == Some("dev") }
+}
+
+#[derive(Debug, Deserialize)]
+struct Resolve {
+    nodes: Vec<Node>,
+}
+
+/// A resolved package and the packages its dependencies resolved to.
+#[derive(Debug, Deserialize)]
+struct Node {
+    id: String,
+    deps: Vec<NodeDep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeDep {
+    /// Name of the crate in code, with a rename applied and `-` replaced by `_`.
+    name: String,
+    pkg: String,
+    dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepKind {
+    kind: Option<String>,
+}
+
+/// A step of the walk through the dependency graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Step<'a> {
+    /// A package is built.
+    Package(&'a str),
+    /// A feature of a package is enabled.
+    Feature(&'a str, &'a str),
+}
+
+/// A dependency requiring a newer toolchain than the package using it.
+#[derive(Debug, PartialEq, Eq)]
+struct Finding {
+    /// The dependency, e.g. `serde v1.0.200`.
+    dependency: String,
+    rust_version: String,
+    /// How the dependency is reached from the package, e.g.
+    /// `pkg v0.1.0 -> pkg/default -> pkg/serde -> serde v1.0.200`.
+    path: String,
+}
+
+/// Walk of the packages and features enabled by building a package with some of its features.
+struct Walk<'a> {
+    packages: HashMap<&'a str, &'a MetadataPackage>,
+    nodes: HashMap<&'a str, &'a Node>,
+    /// Every step reached, with the step which first led to it.
+    parents: HashMap<Step<'a>, Option<Step<'a>>>,
+    queue: VecDeque<Step<'a>>,
+    /// Dependencies enabled for each package, by package ID and dependency key.
+    enabled: HashMap<(&'a str, &'a str), &'a str>,
+    /// Weak `dep?/feature` entries waiting for their dependency to be enabled.
+    weak: HashMap<(&'a str, &'a str), Vec<(&'a str, Step<'a>)>>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(metadata: &'a Metadata, root: &'a str, features: &[&str]) -> Self {
+        let mut walk = Self {
+            packages: metadata.packages.iter().map(|p| (p.id.as_str(), p)).collect(),
+            nodes: metadata.resolve.nodes.iter().map(|n| (n.id.as_str(), n)).collect(),
+            parents: HashMap::new(),
+            queue: VecDeque::new(),
+            enabled: HashMap::new(),
+            weak: HashMap::new(),
+        };
+        walk.visit(Step::Package(root), None);
+        if let Some(package) = walk.packages.get(root).copied() {
+            for (feature, _) in features.iter().filter_map(|f| package.features.get_key_value(*f)) {
+                walk.visit(Step::Feature(root, feature), Some(Step::Package(root)));
+            }
+        }
+        while let Some(step) = walk.queue.pop_front() {
+            match step {
+                Step::Package(id) => walk.build(id),
+                Step::Feature(id, feature) => walk.enable_feature(id, feature),
+            }
+        }
+        walk
+    }
+
+    fn visit(&mut self, step: Step<'a>, parent: Option<Step<'a>>) {
+        if let Entry::Vacant(entry) = self.parents.entry(step) {
+            entry.insert(parent);
+            self.queue.push_back(step);
+        }
+    }
+
+    /// Enable the `default` feature of a package, if it has one.
+    fn request_default(&mut self, id: &'a str, parent: Step<'a>) {
+        if let Some((feature, _)) =
+            self.packages.get(id).and_then(|p| p.features.get_key_value("default"))
+        {
+            self.visit(Step::Feature(id, feature), Some(parent));
+        }
+    }
+
+    /// Building a package enables its required normal and build dependencies.
+    fn build(&mut self, id: &'a str) {
+        let Some(package) = self.packages.get(id).copied() else { return };
+        for dependency in &package.dependencies {
+            if !dependency.optional && !dependency.is_dev() {
+                self.enable_dependency(id, dependency, Step::Package(id));
+            }
+        }
+    }
+
+    /// Enable a dependency of a package along with the features it is declared with.
+    fn enable_dependency(&mut self, id: &'a str, dependency: &'a Dependency, parent: Step<'a>) {
+        let Some(dep_id) = self.resolve(id, dependency) else { return };
+        self.enabled.insert((id, dependency.key()), dep_id);
+        self.visit(Step::Package(dep_id), Some(parent));
+        for feature in &dependency.features {
+            self.visit(Step::Feature(dep_id, feature), Some(parent));
+        }
+        if dependency.uses_default_features {
+            self.request_default(dep_id, parent);
+        }
+        for (feature, weak_parent) in self.weak.remove(&(id, dependency.key())).unwrap_or_default()
+        {
+            self.visit(Step::Feature(dep_id, feature), Some(weak_parent));
+        }
+    }
+
+    /// Follow the entries of an enabled feature.
+    fn enable_feature(&mut self, id: &'a str, feature: &'a str) {
+        let Some(package) = self.packages.get(id).copied() else { return };
+        let Some(entries) = package.features.get(feature) else { return };
+        let step = Step::Feature(id, feature);
+        // Dependencies with a matching key, a dependency may be declared once per kind or target.
+        let dependencies = |key: &'a str| {
+            package.dependencies.iter().filter(move |d| !d.is_dev() && d.key() == key)
+        };
+
+        for entry in entries {
+            if let Some(key) = entry.strip_prefix("dep:") {
+                for dependency in dependencies(key) {
+                    self.enable_dependency(id, dependency, step);
+                }
+            } else if let Some((key, dep_feature)) = entry.split_once('/') {
+                if let Some(key) = key.strip_suffix('?') {
+                    match self.enabled.get(&(id, key)).copied() {
+                        Some(dep_id) => self.visit(Step::Feature(dep_id, dep_feature), Some(step)),
+                        None => self.weak.entry((id, key)).or_default().push((dep_feature, step)),
+                    }
+                } else {
+                    for dependency in dependencies(key) {
+                        self.enable_dependency(id, dependency, step);
+                        if let Some(dep_id) = self.resolve(id, dependency) {
+                            self.visit(Step::Feature(dep_id, dep_feature), Some(step));
+                        }
+                    }
+                }
+            } else {
+                self.visit(Step::Feature(id, entry), Some(step));
+            }
+        }
+    }
+
+    /// ID of the package a dependency resolved to, `None` if it is not built for any platform.
+    fn resolve(&self, id: &str, dependency: &Dependency) -> Option<&'a str> {
+        let crate_name = dependency.rename.as_ref().map(|rename| rename.replace('-', "_"));
+        self.nodes.get(id)?.deps.iter().find_map(|dep| {
+            let package = self.packages.get(dep.pkg.as_str())?;
+            (package.name == dependency.name
+                && dep.dep_kinds.iter().any(|k| k.kind == dependency.kind)
+                && crate_name.as_ref().is_none_or(|name| *name == dep.name))
+            .then_some(dep.pkg.as_str())
+        })
+    }
+
+    /// Human readable path from the root package to `step`.
+    fn path(&self, step: Step<'a>) -> String {
+        let mut steps = vec![step];
+        while let Some(Some(parent)) = self.parents.get(steps.last().expect("at least one step")) {
+            steps.push(*parent);
+        }
+        steps.iter().rev().map(|step| self.describe(*step)).collect::<Vec<_>>().join(" -> ")
+    }
+
+    /// Packages built other than the root, with the step reaching each.
+    fn reached(&self) -> impl Iterator<Item = (Step<'a>, &'a MetadataPackage)> + '_ {
+        self.parents.iter().filter_map(|(step, parent)| match step {
+            Step::Package(id) if parent.is_some() => Some((*step, *self.packages.get(id)?)),
+            _ => None,
+        })
+    }
+
+    fn describe(&self, step: Step) -> String {
+        match step {
+            Step::Package(id) => match self.packages.get(id) {
+                Some(package) => format!("{} v{}", package.name, package.version),
+                None => id.to_owned(),
+            },
+            Step::Feature(id, feature) => match self.packages.get(id) {
+                Some(package) => format!("{}/{}", package.name, feature),
+                None => format!("{}/{}", id, feature),
+            },
+        }
+    }
+}
+
+/// Dependencies of the package `root` with a higher `rust-version` than `msrv`, reachable when
+/// it is built with its default features.
+fn audit(metadata: &Metadata, root: &str, msrv: Version) -> Vec<Finding> {
+    let walk = Walk::new(metadata, root, &["default"]);
+    let mut findings: Vec<Finding> = walk
+        .reached()
+        .filter_map(|(step, package)| {
+            let rust_version = package.rust_version.as_deref()?;
+            (Version::parse(rust_version)? > msrv).then(|| Finding {
+                dependency: format!("{} v{}", package.name, package.version),
+                rust_version: rust_version.to_owned(),
+                path: walk.path(step),
+            })
+        })
+        .collect();
+    findings.sort_by(|a, b| a.dependency.cmp(&b.dependency));
+    findings
+}
+
+/// MSRV overrides of the features of the package `root`, by feature.
+///
+/// A feature needs an override if a dependency which only it enables, built without default
+/// features, has a higher `rust-version` than `msrv`. The override is the highest of them.
+fn infer(metadata: &Metadata, root: &str, msrv: Version) -> BTreeMap<String, String> {
+    let Some(package) = metadata.packages.iter().find(|p| p.id == root) else {
+        return BTreeMap::new();
+    };
+    let base: Vec<&str> =
+        Walk::new(metadata, root, &[]).reached().map(|(_, p)| p.id.as_str()).collect();
+
+    let mut overrides = BTreeMap::new();
+    for feature in package.features.keys().filter(|f| *f != "default") {
+        let walk = Walk::new(metadata, root, &[feature]);
+        let highest = walk
+            .reached()
+            .filter(|(_, p)| !base.contains(&p.id.as_str()))
+            .filter_map(|(_, p)| {
+                let rust_version = p.rust_version.as_deref()?;
+                Some((Version::parse(rust_version)?, rust_version))
+            })
+            .filter(|(version, _)| *version > msrv)
+            .max_by_key(|(version, _)| *version);
+        if let Some((_, rust_version)) = highest {
+            overrides.insert(feature.clone(), rust_version.to_owned());
+        }
+    }
+    overrides
+}
+
+/// MSRV overrides inferred for the features of each package, by package name, under the active
+/// lockfile.
+///
+/// A feature needs an override if it enables optional dependencies with a higher `rust-version`
+/// than the package, directly or through their own dependencies. Packages without a
+/// `rust-version` have none.
+pub fn infer_overrides(
+    sh: &Shell,
+    packages: &[Package],
+) -> Result<HashMap<String, BTreeMap<String, String>>, Box<dyn std::error::Error>> {
+    let metadata = Metadata::read(sh)?;
+    Ok(packages
+        .iter()
+        .filter_map(|package| {
+            let msrv = metadata.msrv(&package.id)?;
+            Some((package.name.clone(), infer(&metadata, &package.id, msrv)))
+        })
+        .collect())
+}
+
+/// Feature to version pairs as overrides, for tests.
+#[cfg(test)]
+pub fn overrides(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+    entries.iter().map(|(f, v)| ((*f).to_owned(), (*v).to_owned())).collect()
+}
+
+/// Compare manual overrides with the inferred ones, describing each stale or missing override.
+fn check_overrides(
+    manual: &BTreeMap<String, String>,
+    inferred: &BTreeMap<String, String>,
+    features: &[String],
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (feature, version) in manual {
+        let problem = match inferred.get(feature) {
+            _ if !features.contains(feature) => "the feature no longer exists".to_owned(),
+            None => "no dependency it enables needs more than the MSRV".to_owned(),
+            Some(needed) if Version::parse(needed) != Version::parse(version) =>
+                format!("the dependencies it enables need {}", needed),
+            Some(_) => continue,
+        };
+        problems.push(format!("stale override {} = \"{}\", {}", feature, version, problem));
+    }
+    for (feature, needed) in inferred.iter().filter(|(f, _)| !manual.contains_key(*f)) {
+        problems.push(format!(
+            "missing override {} = \"{}\", needed by the dependencies it enables",
+            feature, needed
+        ));
+    }
+    problems
+}
+
+/// Check that no dependency reachable with default features requires a newer toolchain than the
+/// package's `rust-version`.
+///
+/// With `overrides`, the `msrv_overrides` of each package are checked against the ones
+/// inferred under every lockfile instead, reporting stale and missing overrides.
+///
+/// # Arguments
+///
+/// * `sh` - The shell environment.
+/// * `packages` - Packages to audit (empty = all packages).
+/// * `lockfiles` - Lockfiles to audit under, empty for every managed lockfile in the workspace.
+/// * `overrides` - Check the manual MSRV overrides instead of the dependencies.
+pub fn run(
+    sh: &Shell,
+    packages: &[String],
+    lockfiles: &[LockFile],
+    overrides: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let _progress = ProgressGuard::new();
+    let root = get_workspace_root(sh)?;
+    let lockfiles = if lockfiles.is_empty() {
+        let present: Vec<LockFile> = ALL_LOCKFILES
+            .into_iter()
+            .filter(|lockfile| root.join(lockfile.filename()).exists())
+            .collect();
+        if present.is_empty() {
+            vec![LockFile::Existing]
+        } else {
+            present
+        }
+    } else {
+        lockfiles.to_vec()
+    };
+
+    let mut report = Vec::new();
+    // Highest override inferred for each feature of each package under any lockfile.
+    let mut inferred: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for lockfile in lockfiles {
+        rbmt_eprintln!("Auditing dependency MSRVs with {}", lockfile.filename());
+        let _lockfile_guard = lockfile.activate(sh)?;
+        let metadata = Metadata::read(sh)?;
+
+        for package in get_workspace_packages(sh, packages)? {
+            let Some(msrv) = metadata.msrv(&package.id) else {
+                rbmt_eprintln!("Skipping {}, no valid rust-version is declared", package.name);
+                continue;
+            };
+            if overrides {
+                let overrides = inferred.entry(package.name.clone()).or_default();
+                for (feature, version) in infer(&metadata, &package.id, msrv) {
+                    let highest = overrides.entry(feature).or_insert_with(|| version.clone());
+                    if Version::parse(&version) > Version::parse(highest) {
+                        *highest = version;
+                    }
+                }
+                continue;
+            }
+
+            let findings = audit(&metadata, &package.id, msrv);
+            if findings.is_empty() {
+                continue;
+            }
+            report.push(format!(
+                "{} (rust-version {}) with {}:",
+                package.name,
+                msrv,
+                lockfile.filename()
+            ));
+            for finding in findings {
+                report.push(format!(
+                    "  {} requires {}: {}",
+                    finding.dependency, finding.rust_version, finding.path
+                ));
+            }
+        }
+    }
+
+    if overrides {
+        for package in get_workspace_packages(sh, packages)? {
+            let Some(inferred) = inferred.get(&package.name) else { continue };
+            let manual = TestConfig::load(&package.dir)?.msrv_overrides();
+            let problems = check_overrides(&manual, inferred, &package.features);
+            if !problems.is_empty() {
+                report.push(format!("{}:", package.name));
+                report.extend(problems.into_iter().map(|problem| format!("  {}", problem)));
+            }
+        }
+        if !report.is_empty() {
+            return Err(format!(
+                "MSRV overrides in [package.metadata.rbmt.test] do not match the dependencies:\n{}",
+                report.join("\n")
+            )
+            .into());
+        }
+        rbmt_eprintln!("MSRV overrides match the dependencies");
+        return Ok(());
+    }
+
+    if !report.is_empty() {
+        return Err(format!(
+            "Dependencies reachable with default features require a newer toolchain than the \
+             MSRV, gate them behind a feature or raise the rust-version:\n{}",
+            report.join("\n")
+        )
+        .into());
+    }
+    rbmt_eprintln!("No dependency requires a newer toolchain than the MSRV");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(
+        name: &str,
+        rust_version: Option<&str>,
+        dependencies: &[serde_json::Value],
+        features: &serde_json::Value,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "id": format!("{} 1.0.0", name),
+            "name": name,
+            "version": "1.0.0",
+            "rust_version": rust_version,
+            "dependencies": dependencies,
+            "features": features,
+        })
+    }
+
+    fn dependency(name: &str, kind: Option<&str>, optional: bool) -> serde_json::Value {
+        serde_json::json!({
+            "name": name,
+            "rename": null,
+            "kind": kind,
+            "optional": optional,
+            "uses_default_features": true,
+            "features": [],
+        })
+    }
+
+    fn node(name: &str, deps: &[(&str, Option<&str>)]) -> serde_json::Value {
+        let deps: Vec<_> = deps
+            .iter()
+            .map(|(dep, kind)| {
+                serde_json::json!({
+                    "name": dep.replace('-', "_"),
+                    "pkg": format!("{} 1.0.0", dep),
+                    "dep_kinds": [{ "kind": kind }],
+                })
+            })
+            .collect();
+        serde_json::json!({ "id": format!("{} 1.0.0", name), "deps": deps })
+    }
+
+    fn sample_metadata() -> Metadata {
+        serde_json::from_value(serde_json::json!({
+            "packages": [
+                package(
+                    "consumer",
+                    Some("1.60.0"),
+                    &[
+                        dependency("encoding", None, true),
+                        dependency("derive", None, true),
+                        dependency("fuzz", Some("dev"), false),
+                    ],
+                    &serde_json::json!({
+                        "default": ["std"],
+                        "std": ["encoding?/std", "dep:encoding"],
+                        "derive": ["dep:derive"],
+                        "encoding": ["dep:encoding"],
+                    }),
+                ),
+                package(
+                    "encoding",
+                    Some("1.63"),
+                    &[dependency("hashes", None, false)],
+                    &serde_json::json!({ "std": [] }),
+                ),
+                package("hashes", Some("1.74.0"), &[], &serde_json::json!({})),
+                package("derive", Some("1.80.0"), &[], &serde_json::json!({})),
+                package("fuzz", Some("1.80.0"), &[], &serde_json::json!({})),
+            ],
+            "resolve": { "nodes": [
+                node("consumer", &[("encoding", None), ("derive", None), ("fuzz", Some("dev"))]),
+                node("encoding", &[("hashes", None)]),
+                node("hashes", &[]),
+                node("derive", &[]),
+                node("fuzz", &[]),
+            ]},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn audit_follows_default_features() {
+        let metadata = sample_metadata();
+        let findings = audit(&metadata, "consumer 1.0.0", Version::parse("1.60.0").unwrap());
+        let found: Vec<(&str, &str)> =
+            findings.iter().map(|f| (f.dependency.as_str(), f.path.as_str())).collect();
+        // The optional `derive` and the dev-dependency `fuzz` are not reached.
+        assert_eq!(
+            found,
+            [
+                (
+                    "encoding v1.0.0",
+                    "consumer v1.0.0 -> consumer/default -> consumer/std -> encoding v1.0.0"
+                ),
+                (
+                    "hashes v1.0.0",
+                    "consumer v1.0.0 -> consumer/default -> consumer/std -> encoding v1.0.0 \
+                     -> hashes v1.0.0"
+                ),
+            ]
+        );
+        assert_eq!(findings[1].rust_version, "1.74.0");
+
+        assert!(audit(&metadata, "consumer 1.0.0", Version::parse("1.74.0").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn infer_overrides_of_features() {
+        let metadata = sample_metadata();
+        let overrides = infer(&metadata, "consumer 1.0.0", Version::parse("1.60.0").unwrap());
+        let overrides: Vec<(&str, &str)> =
+            overrides.iter().map(|(f, v)| (f.as_str(), v.as_str())).collect();
+        // `std` enables `encoding` which needs 1.63, but its dependency `hashes` needs 1.74.
+        assert_eq!(overrides, [("derive", "1.80.0"), ("encoding", "1.74.0"), ("std", "1.74.0")]);
+    }
+
+    #[test]
+    fn check_manual_overrides() {
+        let map = overrides;
+        let features: Vec<String> = ["derive", "std", "rand"].map(str::to_owned).to_vec();
+        let inferred = map(&[("derive", "1.80.0"), ("std", "1.74.0")]);
+
+        let manual = map(&[("derive", "1.80"), ("std", "1.74.0")]);
+        assert!(check_overrides(&manual, &inferred, &features).is_empty());
+
+        let manual = map(&[("derive", "1.70.0"), ("rand", "1.63.0"), ("gone", "1.63.0")]);
+        assert_eq!(
+            check_overrides(&manual, &inferred, &features),
+            [
+                "stale override derive = \"1.70.0\", the dependencies it enables need 1.80.0",
+                "stale override gone = \"1.63.0\", the feature no longer exists",
+                "stale override rand = \"1.63.0\", no dependency it enables needs more than the MSRV",
+                "missing override std = \"1.74.0\", needed by the dependencies it enables",
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Process-wide plan of the commands a task would run.
+//!
+//! With the global `--plan` flag, commands run through [`CmdExt`](crate::environment::CmdExt)
+//! are recorded here instead of being run, along with the directory, environment, and
+//! toolchain they would run with. Commands which only inspect the workspace, such as
+//! `cargo metadata`, still run so the plan matches what the task would do.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use xshell::Cmd;
+
+/// Output format of the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlanFormat {
+    /// One command per line.
+    Text,
+    /// A JSON object with a `commands` array.
+    Json,
+}
+
+/// A command which would have run.
+#[derive(Debug)]
+struct PlannedCommand {
+    /// Toolchain the command would run with, e.g. `1.74.0` or `nightly-2026-02-21`.
+    toolchain: String,
+    dir: PathBuf,
+    /// Environment variables set for the command by rbmt.
+    env: Vec<(String, String)>,
+    program: String,
+    args: Vec<String>,
+}
+
+/// State of the plan, `None` unless planning.
+#[derive(Debug, Default)]
+struct Plan {
+    toolchain: Option<String>,
+    commands: Vec<PlannedCommand>,
+}
+
+/// Global by necessity, commands are recorded deep inside every task.
+fn plan() -> &'static Mutex<Option<Plan>> {
+    static PLAN: OnceLock<Mutex<Option<Plan>>> = OnceLock::new();
+    PLAN.get_or_init(|| Mutex::new(None))
+}
+
+/// Record commands from now on instead of running them.
+pub fn start() {
+    if let Ok(mut plan) = plan().lock() {
+        *plan = Some(Plan::default());
+    }
+}
+
+/// Returns `true` if commands are being recorded instead of run.
+pub fn is_active() -> bool { plan().lock().is_ok_and(|plan| plan.is_some()) }
+
+/// Set the toolchain subsequent commands would run with.
+///
+/// Tasks are planned sequentially, so the toolchain last prepared applies to the commands
+/// which follow it.
+pub fn set_toolchain(toolchain: &str) {
+    if let Ok(mut plan) = plan().lock() {
+        if let Some(plan) = plan.as_mut() {
+            plan.toolchain = Some(toolchain.to_owned());
+        }
+    }
+}
+
+/// Record a command instead of running it.
+pub fn record(cmd: Cmd<'_>) {
+    let command = Command::from(cmd);
+
+    let mut env: Vec<(String, String)> = command
+        .get_envs()
+        .filter_map(|(key, value)| {
+            Some((key.to_string_lossy().into_owned(), value?.to_string_lossy().into_owned()))
+        })
+        .collect();
+    // The shell's environment is unordered.
+    env.sort();
+
+    let Ok(mut plan) = plan().lock() else { return };
+    let Some(plan) = plan.as_mut() else { return };
+    let command = PlannedCommand {
+        toolchain: plan.toolchain.clone().unwrap_or_else(|| "default".to_owned()),
+        dir: command.get_current_dir().map(Path::to_path_buf).unwrap_or_default(),
+        env,
+        program: command.get_program().to_string_lossy().into_owned(),
+        args: command.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect(),
+    };
+    plan.commands.push(command);
+}
+
+/// Print the recorded commands to stdout, with directories relative to `root`.
+pub fn print(root: &Path, format: PlanFormat) {
+    let Ok(plan) = plan().lock() else { return };
+    let Some(plan) = plan.as_ref() else { return };
+    let relative = |dir: &Path| match dir.strip_prefix(root) {
+        Ok(dir) if dir.as_os_str().is_empty() => ".".to_owned(),
+        Ok(dir) => dir.display().to_string(),
+        Err(_) => dir.display().to_string(),
+    };
+
+    match format {
+        PlanFormat::Text =>
+            for command in &plan.commands {
+                let mut line = format!("{} [{}]", relative(&command.dir), command.toolchain);
+                for (key, value) in &command.env {
+                    let _ = write!(line, " {}={}", key, quote(value));
+                }
+                line.push(' ');
+                line.push_str(&command.program);
+                for arg in &command.args {
+                    line.push(' ');
+                    line.push_str(&quote(arg));
+                }
+                println!("{}", line);
+            },
+        PlanFormat::Json => {
+            let commands: Vec<_> = plan
+                .commands
+                .iter()
+                .map(|command| {
+                    serde_json::json!({
+                        "toolchain": command.toolchain,
+                        "dir": relative(&command.dir),
+                        "env": command
+                            .env
+                            .iter()
+                            .map(|(key, value)| (key.clone(), value.as_str().into()))
+                            .collect::<serde_json::Map<_, _>>(),
+                        "program": command.program,
+                        "args": command.args,
+                    })
+                })
+                .collect();
+            println!("{:#}", serde_json::json!({ "commands": commands }));
+        }
+    }
+}
+
+/// Quote a word containing whitespace or quotes for a shell.
+fn quote(word: &str) -> String {
+    if word.is_empty() || word.chars().any(|c| c.is_whitespace() || c == '"' || c == '\'') {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    } else {
+        word.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quote_words_for_a_shell() {
+        assert_eq!(quote("--all-features"), "--all-features");
+        assert_eq!(quote("-D warnings"), "'-D warnings'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+    }
+}
@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Machine-readable reports of test runs.
+//!
+//! A run is reported as the units (examples, feature sets, target builds) tested for each
+//! package at each commit, in either a JSON document or `JUnit` XML for CI dashboards.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::environment::CommandError;
+
+/// Format of a test report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// JSON document grouped by commit and package.
+    Json,
+    /// `JUnit` XML with a test suite per package per commit.
+    Junit,
+}
+
+/// Where and how to write a test report.
+#[derive(Debug, Clone)]
+pub struct ReportTarget {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+/// What a unit of a test run exercised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitKind {
+    /// An example run.
+    Example,
+    /// A build and test of a feature set.
+    FeatureSet,
+    /// A cross-compilation build for a target.
+    Target,
+}
+
+impl UnitKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Example => "example",
+            Self::FeatureSet => "feature-set",
+            Self::Target => "target",
+        }
+    }
+}
+
+/// Why a unit failed.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub message: String,
+    /// The command line which failed, if the failure came from running a command.
+    pub command: Option<String>,
+}
+
+impl Failure {
+    pub fn from_error(e: &(dyn std::error::Error + 'static)) -> Self {
+        Self {
+            message: e.to_string(),
+            command: e.downcast_ref::<CommandError>().map(|e| e.command.clone()),
+        }
+    }
+}
+
+/// How a unit of a test run ended.
+#[derive(Debug, Clone)]
+pub enum UnitStatus {
+    Passed,
+    /// Skipped because it passed in a previous run.
+    Resumed,
+    Failed(Failure),
+}
+
+/// A single unit of a test run.
+#[derive(Debug, Clone)]
+pub struct UnitOutcome {
+    pub kind: UnitKind,
+    /// Human readable name, unique within the package.
+    pub name: String,
+    pub status: UnitStatus,
+    pub duration: Duration,
+}
+
+impl UnitOutcome {
+    /// Outcome of a unit which ran from `start` and ended with `result`.
+    pub fn finished(
+        kind: UnitKind,
+        name: String,
+        start: Instant,
+        result: &Result<(), Box<dyn std::error::Error>>,
+    ) -> Self {
+        let status = match result {
+            Ok(()) => UnitStatus::Passed,
+            Err(e) => UnitStatus::Failed(Failure::from_error(e.as_ref())),
+        };
+        Self { kind, name, status, duration: start.elapsed() }
+    }
+
+    /// Outcome of a unit skipped because it passed in a previous run.
+    pub fn resumed(kind: UnitKind, name: String) -> Self {
+        Self { kind, name, status: UnitStatus::Resumed, duration: Duration::ZERO }
+    }
+}
+
+/// The units tested at a single commit.
+pub struct CommitUnits<'a> {
+    pub commit: &'a str,
+    /// Lockfile and toolchain the commit was tested with, if the run tested several.
+    pub combination: Option<String>,
+    /// Package name paired with its units.
+    pub packages: Vec<(&'a str, &'a [UnitOutcome])>,
+}
+
+/// Write a report of the given commits.
+pub fn write(
+    target: &ReportTarget,
+    commits: &[CommitUnits],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = match target.format {
+        ReportFormat::Json => serde_json::to_string_pretty(&to_json(commits))?,
+        ReportFormat::Junit => to_junit(commits),
+    };
+    if let Some(parent) = target.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&target.path, contents)?;
+    Ok(())
+}
+
+/// Unit durations in seconds, keyed by package, kind, and name.
+pub type Durations = HashMap<(String, String, String), f64>;
+
+/// Durations of the units which ran in a JSON report.
+///
+/// A unit reported at several commits takes its longest duration. Resumed units did not run and
+/// are left out.
+pub fn read_durations(path: &Path) -> Result<Durations, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read report {}: {}", path.display(), e))?;
+    durations(&serde_json::from_str(&contents)?)
+}
+
+fn durations(report: &serde_json::Value) -> Result<Durations, Box<dyn std::error::Error>> {
+    let mut durations = HashMap::new();
+    let commits = report["commits"].as_array().ok_or("Expected a JSON test report")?;
+    for package in commits.iter().filter_map(|c| c["packages"].as_array()).flatten() {
+        let Some(package_name) = package["name"].as_str() else { continue };
+        for unit in package["units"].as_array().into_iter().flatten() {
+            let (Some(kind), Some(name), Some(duration)) =
+                (unit["kind"].as_str(), unit["name"].as_str(), unit["duration_secs"].as_f64())
+            else {
+                continue;
+            };
+            if unit["status"] == "resumed" {
+                continue;
+            }
+            let key = (package_name.to_owned(), kind.to_owned(), name.to_owned());
+            let entry = durations.entry(key).or_insert(duration);
+            *entry = entry.max(duration);
+        }
+    }
+    Ok(durations)
+}
+
+fn to_json(commits: &[CommitUnits]) -> serde_json::Value {
+    let commits: Vec<_> = commits
+        .iter()
+        .map(|commit| {
+            let packages: Vec<_> = commit
+                .packages
+                .iter()
+                .map(|(name, units)| {
+                    let units: Vec<_> = units
+                        .iter()
+                        .map(|unit| {
+                            let (status, failure) = match &unit.status {
+                                UnitStatus::Passed => ("passed", None),
+                                UnitStatus::Resumed => ("resumed", None),
+                                UnitStatus::Failed(failure) => ("failed", Some(failure)),
+                            };
+                            serde_json::json!({
+                                "kind": unit.kind.as_str(),
+                                "name": unit.name,
+                                "status": status,
+                                "duration_secs": unit.duration.as_secs_f64(),
+                                "error": failure.map(|f| &f.message),
+                                "command": failure.and_then(|f| f.command.as_ref()),
+                            })
+                        })
+                        .collect();
+                    serde_json::json!({ "name": name, "units": units })
+                })
+                .collect();
+            serde_json::json!({
+                "commit": commit.commit,
+                "combination": commit.combination,
+                "packages": packages,
+            })
+        })
+        .collect();
+    serde_json::json!({ "commits": commits })
+}
+
+fn to_junit(commits: &[CommitUnits]) -> String {
+    let mut suites = String::new();
+    let (mut total_tests, mut total_failures, mut total_time) = (0, 0, Duration::ZERO);
+
+    for commit in commits {
+        let mut suite = commit.commit.get(..12).unwrap_or(commit.commit).to_owned();
+        if let Some(combination) = &commit.combination {
+            let _ = write!(suite, " ({})", combination);
+        }
+        for (package, units) in &commit.packages {
+            let failures =
+                units.iter().filter(|u| matches!(u.status, UnitStatus::Failed(_))).count();
+            let skipped = units.iter().filter(|u| matches!(u.status, UnitStatus::Resumed)).count();
+            let time: Duration = units.iter().map(|u| u.duration).sum();
+            total_tests += units.len();
+            total_failures += failures;
+            total_time += time;
+
+            let _ = writeln!(
+                suites,
+                r#"  <testsuite name="{}@{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+                xml_escape(package),
+                xml_escape(&suite),
+                units.len(),
+                failures,
+                skipped,
+                time.as_secs_f64()
+            );
+            for unit in *units {
+                let _ = write!(
+                    suites,
+                    r#"    <testcase classname="{}" name="{}: {}" time="{:.3}""#,
+                    xml_escape(package),
+                    unit.kind.as_str(),
+                    xml_escape(&unit.name),
+                    unit.duration.as_secs_f64()
+                );
+                match &unit.status {
+                    UnitStatus::Passed => suites.push_str("/>\n"),
+                    UnitStatus::Resumed => suites.push_str(
+                        ">\n      <skipped message=\"passed in a previous run\"/>\n    </testcase>\n",
+                    ),
+                    UnitStatus::Failed(failure) => {
+                        let _ = writeln!(
+                            suites,
+                            ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                            xml_escape(&failure.message),
+                            xml_escape(failure.command.as_deref().unwrap_or(&failure.message))
+                        );
+                    }
+                }
+            }
+            suites.push_str("  </testsuite>\n");
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"cargo-rbmt test\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n{}</testsuites>\n",
+        total_tests,
+        total_failures,
+        total_time.as_secs_f64(),
+        suites
+    )
+}
+
+/// Escape text for use in XML attributes and content.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_units() -> Vec<UnitOutcome> {
+        vec![
+            UnitOutcome {
+                kind: UnitKind::FeatureSet,
+                name: "all features".to_owned(),
+                status: UnitStatus::Passed,
+                duration: Duration::from_millis(1500),
+            },
+            UnitOutcome::resumed(UnitKind::Example, "bip32:serde rand".to_owned()),
+            UnitOutcome {
+                kind: UnitKind::FeatureSet,
+                name: r#"feature set ["a", "b"]"#.to_owned(),
+                status: UnitStatus::Failed(Failure {
+                    message: "Command failed: exit status: 101".to_owned(),
+                    command: Some("cargo test --features a,b".to_owned()),
+                }),
+                duration: Duration::from_millis(250),
+            },
+        ]
+    }
+
+    #[test]
+    fn junit_report() {
+        let units = sample_units();
+        let commits = [CommitUnits {
+            commit: "0123456789abcdef",
+            combination: None,
+            packages: vec![("pkg", &units)],
+        }];
+        let xml = to_junit(&commits);
+
+        assert!(xml.contains(
+            r#"<testsuites name="cargo-rbmt test" tests="3" failures="1" time="1.750">"#
+        ));
+        assert!(xml.contains(
+            r#"<testsuite name="pkg@0123456789ab" tests="3" failures="1" skipped="1" time="1.750">"#
+        ));
+        assert!(xml.contains(
+            r#"<testcase classname="pkg" name="feature-set: all features" time="1.500"/>"#
+        ));
+        assert!(xml.contains(r#"<skipped message="passed in a previous run"/>"#));
+        assert!(xml.contains(r#"name="feature-set: feature set [&quot;a&quot;, &quot;b&quot;]""#));
+        assert!(xml.contains(
+            r#"<failure message="Command failed: exit status: 101">cargo test --features a,b</failure>"#
+        ));
+    }
+
+    #[test]
+    fn json_report() {
+        let units = sample_units();
+        let commits = [CommitUnits {
+            commit: "0123456789abcdef",
+            combination: Some("lockfile minimal, toolchain msrv".to_owned()),
+            packages: vec![("pkg", &units)],
+        }];
+        let json = to_json(&commits);
+
+        let units = &json["commits"][0]["packages"][0]["units"];
+        assert_eq!(json["commits"][0]["commit"], "0123456789abcdef");
+        assert_eq!(json["commits"][0]["combination"], "lockfile minimal, toolchain msrv");
+        assert_eq!(units[0]["status"], "passed");
+        assert_eq!(units[0]["duration_secs"], 1.5);
+        assert_eq!(units[1]["kind"], "example");
+        assert_eq!(units[1]["status"], "resumed");
+        assert_eq!(units[2]["status"], "failed");
+        assert_eq!(units[2]["command"], "cargo test --features a,b");
+    }
+
+    #[test]
+    fn durations_from_json_report() {
+        let units = sample_units();
+        let mut slower = sample_units();
+        slower[0].duration = Duration::from_secs(3);
+        let commits = [
+            CommitUnits {
+                commit: "0123456789abcdef",
+                combination: None,
+                packages: vec![("pkg", &units)],
+            },
+            CommitUnits {
+                commit: "fedcba9876543210",
+                combination: None,
+                packages: vec![("pkg", &slower)],
+            },
+        ];
+        assert!(durations(&serde_json::json!({})).is_err());
+        let durations = durations(&to_json(&commits)).unwrap();
+
+        let key = |kind: &str, name: &str| ("pkg".to_owned(), kind.to_owned(), name.to_owned());
+        assert_eq!(durations.len(), 2);
+        assert_eq!(durations[&key("feature-set", "all features")], 3.0);
+        assert!(!durations.contains_key(&key("example", "bip32:serde rand")));
+    }
+}
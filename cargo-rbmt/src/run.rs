@@ -4,52 +4,68 @@
 
 use xshell::Shell;
 
+use crate::combination::Combination;
 use crate::environment::{cargo_cmd, get_workspace_packages, CmdExt, ProgressGuard};
 use crate::git;
-use crate::lock::LockFile;
-use crate::toolchain::{prepare_toolchain, Toolchain};
+use crate::toolchain::prepare_toolchain;
 
-/// Run a cargo command with the specified toolchain and lockfile.
+/// Run a cargo command with each of the specified lockfile and toolchain combinations.
+///
+/// Combinations run in order and stop at the first failure.
 ///
 /// # Arguments
 ///
 /// * `sh` - The shell environment.
-/// * `lockfile` - Which lockfile variant to use (minimal, recent, or existing).
-/// * `toolchain` - Which toolchain to use (nightly, stable, or msrv).
+/// * `combinations` - Which lockfile variant (minimal, recent, or existing) and toolchain
+///   (nightly, stable, or msrv) to run with.
 /// * `baseline` - Optional baseline ref for running the command on multiple commits.
 /// * `packages` - Packages to run the command on (empty = all packages).
 /// * `cargo_args` - Arguments to pass to cargo (everything after `--`).
 pub fn run(
     sh: &Shell,
-    lockfile: LockFile,
-    toolchain: Toolchain,
+    combinations: &[Combination],
     baseline: Option<&str>,
     packages: &[String],
     cargo_args: &[String],
 ) -> Result<(), Box<dyn std::error::Error>> {
     let _progress = ProgressGuard::new();
 
-    git::for_each_commit(sh, lockfile, baseline, |sh| {
-        // Set toolchain and packages per-commit.
-        prepare_toolchain(sh, toolchain)?;
-        let resolved_packages = get_workspace_packages(sh, packages)?;
-
-        let mut cmd = cargo_cmd(sh);
-        // Add cargo subcommand (first arg in cargo_args).
-        if let Some(subcommand) = cargo_args.first() {
-            cmd = cmd.arg(subcommand);
-        }
-        // Add package flags after subcommand, but before other arguments.
-        for pkg in &resolved_packages {
-            cmd = cmd.arg("-p").arg(&pkg.id);
-        }
-        // Add remaining arguments (skip first which was the subcommand).
-        if cargo_args.len() > 1 {
-            cmd = cmd.args(&cargo_args[1..]);
+    let several = combinations.len() > 1;
+    for &combination in combinations {
+        let Combination { lockfile, toolchain } = combination;
+        if several {
+            rbmt_eprintln!("Running with {}", combination);
         }
+        // A fresh shell, so the toolchain pinned for one combination does not leak into the next.
+        let combination_sh = Shell::new()?;
+        combination_sh.change_dir(sh.current_dir());
 
-        cmd.run_with_capture()
-    })?;
+        let result = git::for_each_commit(&combination_sh, lockfile, baseline, |sh| {
+            // Set toolchain and packages per-commit.
+            prepare_toolchain(sh, toolchain)?;
+            let resolved_packages = get_workspace_packages(sh, packages)?;
+
+            let mut cmd = cargo_cmd(sh);
+            // Add cargo subcommand (first arg in cargo_args).
+            if let Some(subcommand) = cargo_args.first() {
+                cmd = cmd.arg(subcommand);
+            }
+            // Add package flags after subcommand, but before other arguments.
+            for pkg in &resolved_packages {
+                cmd = cmd.arg("-p").arg(&pkg.id);
+            }
+            // Add remaining arguments (skip first which was the subcommand).
+            if cargo_args.len() > 1 {
+                cmd = cmd.args(&cargo_args[1..]);
+            }
+
+            cmd.run_with_capture()
+        });
+        match result {
+            Err(e) if several => return Err(format!("Failed with {}: {}", combination, e).into()),
+            result => result?,
+        }
+    }
 
     Ok(())
 }
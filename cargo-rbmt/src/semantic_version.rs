@@ -2,6 +2,8 @@
 
 //! Semantic version parsing and comparison.
 
+use std::fmt;
+
 /// Represents a semantic version (major.minor.patch).
 ///
 /// The derived ordering depends on the order of the fields to match semantic version rules.
@@ -28,6 +30,12 @@ impl Version {
     }
 }
 
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: MIT AND Apache-2.0
+
+//! Deterministic splitting of a test run across CI jobs.
+//!
+//! Every job plans the same units in the same order, so each one can work out its own share
+//! without coordinating with the others. Units are weighed by their duration in an earlier
+//! report when known, and handed out longest first to the least loaded shard.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// One of `count` shards of a run, written as `index/count` with `index` counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    /// Zero-based index of this shard.
+    index: usize,
+    count: usize,
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard '{}', expected <index>/<count> such as 1/4", s);
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: usize = index.trim().parse().map_err(|_| invalid())?;
+        let count: usize = count.trim().parse().map_err(|_| invalid())?;
+        if index == 0 || index > count {
+            return Err(format!("Shard index must be between 1 and {}, got {}", count, index));
+        }
+        Ok(Self { index: index - 1, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index + 1, self.count)
+    }
+}
+
+impl Shard {
+    /// Select the units of this shard, given the duration in seconds of each unit if known.
+    ///
+    /// Units without a duration weigh the average of the known ones, or all weigh the same if
+    /// none are known, which balances the number of units per shard. Equal weights are handed
+    /// out in the given order, so the split only depends on the units and their durations.
+    pub fn select(&self, durations: &[Option<f64>]) -> Vec<bool> {
+        let known: Vec<f64> = durations.iter().flatten().copied().collect();
+        #[allow(clippy::cast_precision_loss)] // Only a weight, precision does not matter.
+        let default =
+            if known.is_empty() { 1.0 } else { known.iter().sum::<f64>() / known.len() as f64 };
+        let weights: Vec<f64> = durations.iter().map(|d| d.unwrap_or(default)).collect();
+
+        let mut order: Vec<usize> = (0..weights.len()).collect();
+        // Stable, so equal weights keep their order.
+        order.sort_by(|&a, &b| weights[b].total_cmp(&weights[a]));
+
+        let mut loads = vec![0.0_f64; self.count];
+        let mut selected = vec![false; weights.len()];
+        for unit in order {
+            let (shard, _) = loads
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .expect("at least one shard");
+            loads[shard] += weights[unit];
+            selected[unit] = shard == self.index;
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_shard() {
+        assert_eq!("2/3".parse::<Shard>().unwrap().to_string(), "2/3");
+        assert!("0/3".parse::<Shard>().is_err());
+        assert!("4/3".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn shards_partition_units() {
+        let durations = [Some(10.0), Some(1.0), None, Some(4.0), Some(5.0), None, Some(2.0)];
+        let shards: Vec<Vec<bool>> = (1..=3)
+            .map(|i| format!("{}/3", i).parse::<Shard>().unwrap().select(&durations))
+            .collect();
+        for unit in 0..durations.len() {
+            assert_eq!(shards.iter().filter(|s| s[unit]).count(), 1);
+        }
+        // The longest unit gets a shard of its own.
+        assert_eq!(shards[0], [true, false, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn unknown_durations_balance_counts() {
+        let durations = [None; 7];
+        let counts: Vec<usize> = (1..=3)
+            .map(|i| {
+                let shard = format!("{}/3", i).parse::<Shard>().unwrap();
+                shard.select(&durations).iter().filter(|&&s| s).count()
+            })
+            .collect();
+        assert_eq!(counts, [3, 2, 2]);
+    }
+}
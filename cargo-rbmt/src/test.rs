@@ -6,30 +6,54 @@
 //! and catch any issues involving `cfg(test)` somehow gating required code.
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashSet;
-use std::fmt;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
 use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use std::{fmt, thread};
 
 use serde::Deserialize;
 use xshell::Shell;
 
+use crate::combination::Combination;
 use crate::environment::{
-    cargo_cmd, get_workspace_packages, CmdExt, Package, PackageManifest, ProgressGuard,
+    cargo_cmd, get_target_dir, get_workspace_packages, get_workspace_root, CmdExt, CommandError,
+    Package, PackageManifest, ProgressGuard,
 };
-use crate::git;
-use crate::lock::LockFile;
-use crate::toolchain::{prepare_toolchain_with_override, Toolchain};
+use crate::journal::{self, Journal};
+use crate::report::{self, CommitUnits, ReportTarget, UnitKind, UnitOutcome, UnitStatus};
+use crate::semantic_version::Version;
+use crate::shard::Shard;
+use crate::toolchain::{
+    add_target, host_target, prepare_toolchain_with_override, Toolchain, RUSTUP_TOOLCHAIN,
+};
+use crate::{git, msrv, plan, tree};
 
 /// Feature to MSRV version mappings for override during testing.
 #[derive(Debug, Clone, Default, Deserialize)]
-struct MsrvOverrides(std::collections::HashMap<String, String>);
+#[serde(from = "HashMap<String, String>")]
+struct MsrvOverrides {
+    /// Overrides configured in the manifest.
+    manual: HashMap<String, String>,
+    /// Overrides inferred from the `rust-version` of the dependencies each feature enables, see
+    /// [`msrv::infer_overrides`]. Only used for features without a manual override.
+    inferred: BTreeMap<String, String>,
+}
+
+impl From<HashMap<String, String>> for MsrvOverrides {
+    fn from(manual: HashMap<String, String>) -> Self { Self { manual, inferred: BTreeMap::new() } }
+}
 
 impl MsrvOverrides {
     /// Find an MSRV override for the given features.
     ///
     /// Returns the override version if any feature in the set has an override, `None` otherwise.
-    /// Returns an error if multiple features have conflicting MSRV overrides.
+    /// Returns an error if multiple features have conflicting manual MSRV overrides. Inferred
+    /// overrides never conflict, the highest one is needed.
     ///
     /// # Arguments
     ///
@@ -39,22 +63,39 @@ impl MsrvOverrides {
         let overrides: HashSet<_> = match features {
             None => {
                 // Get all configured overrides.
-                self.0.values().map(std::string::String::as_str).collect()
+                self.manual.values().map(std::string::String::as_str).collect()
             }
             Some(feature_list) => {
                 // Get overrides for specific features.
                 feature_list
                     .iter()
-                    .filter_map(|f| self.0.get(f).map(std::string::String::as_str))
+                    .filter_map(|f| self.manual.get(f).map(std::string::String::as_str))
                     .collect()
             }
         };
 
-        match overrides.len() {
-            0 => Ok(None),
-            1 => Ok(overrides.into_iter().next()),
-            _ => Err(format!("Conflicting MSRV overrides: {:?}", overrides).into()),
-        }
+        let manual = match overrides.len() {
+            0 => None,
+            1 => overrides.into_iter().next(),
+            _ => return Err(format!("Conflicting MSRV overrides: {:?}", overrides).into()),
+        };
+        let inferred = self
+            .inferred
+            .iter()
+            .filter(|(feature, _)| {
+                !self.manual.contains_key(*feature)
+                    && features.is_none_or(|features| features.contains(feature))
+            })
+            .filter_map(|(_, version)| Some((Version::parse(version)?, version.as_str())))
+            .max_by_key(|(version, _)| *version);
+
+        Ok(match (manual, inferred) {
+            (Some(manual), Some((inferred, version)))
+                if Version::parse(manual).is_some_and(|manual| manual < inferred) =>
+                Some(version),
+            (Some(manual), _) => Some(manual),
+            (None, inferred) => inferred.map(|(_, version)| version),
+        })
     }
 }
 
@@ -67,6 +108,9 @@ enum SampleStrategy {
     Log,
     /// Test all possible feature combinations (excluding none, individual, and all).
     All,
+    /// Test a covering array in which every pair (or triple) of features is seen in every
+    /// on/off combination.
+    Pairwise,
 }
 
 impl SampleStrategy {
@@ -78,10 +122,18 @@ impl SampleStrategy {
     ///   If no commit ID is available, returns an empty vector.
     /// * **All**: Generates all (~`2^n`) possible combinations. Excludes empty, individual, and
     ///   full set which are tested elsewhere.
-    fn generate_subsets(self, features: &[String], commit: Option<String>) -> Vec<Vec<String>> {
+    /// * **Pairwise**: Generates a covering array of the given `strength`, independent of the
+    ///   commit ID. Excludes empty, individual, and full set which are tested elsewhere.
+    fn generate_subsets(
+        self,
+        features: &[String],
+        commit: Option<String>,
+        strength: usize,
+    ) -> Vec<Vec<String>> {
         match self {
             Self::Log => generate_log_sampled_subsets(features, commit),
             Self::All => generate_all_subsets(features),
+            Self::Pairwise => generate_covering_subsets(features, strength),
         }
     }
 }
@@ -156,6 +208,169 @@ fn generate_all_subsets(features: &[String]) -> Vec<Vec<String>> {
     subsets
 }
 
+/// Generate feature subsets which form a covering array of the given strength.
+///
+/// Every combination of `strength` features is seen in each of its `2^strength` on/off states
+/// across the returned subsets together with the empty, individual, and full sets, which are
+/// tested elsewhere and so are never returned. The array grows roughly with the logarithm of the
+/// number of features, rather than exponentially like the `all` strategy.
+///
+/// The array is built greedily one row at a time. Each row starts from the first uncovered
+/// combination and then sets each remaining feature to whichever state covers the most
+/// uncovered combinations with the features set so far. There is no randomness, so the result
+/// only depends on the (sorted) features.
+fn generate_covering_subsets(features: &[String], strength: usize) -> Vec<Vec<String>> {
+    let n = features.len();
+    let t = strength.min(n);
+    if t < 2 {
+        return Vec::new();
+    }
+
+    // Uncovered combinations as (feature indices, on/off state of each as a bitmask).
+    let mut uncovered: BTreeSet<(Vec<usize>, u32)> = BTreeSet::new();
+    for tuple in combinations(&(0..n).collect::<Vec<_>>(), t) {
+        for state in 0..1u32 << t {
+            uncovered.insert((tuple.clone(), state));
+        }
+    }
+
+    let mut rows: Vec<Vec<bool>> = Vec::new();
+    while let Some((seed, seed_state)) = uncovered.first().cloned() {
+        let mut row: Vec<Option<bool>> = vec![None; n];
+        for (bit, &feature) in seed.iter().enumerate() {
+            row[feature] = Some(seed_state >> bit & 1 == 1);
+        }
+
+        for feature in 0..n {
+            if row[feature].is_some() {
+                continue;
+            }
+            let assigned: Vec<usize> = (0..n).filter(|&i| row[i].is_some()).collect();
+            let newly_covered = |on: bool| {
+                combinations(&assigned, t - 1)
+                    .into_iter()
+                    .filter(|others| {
+                        let mut tuple = others.clone();
+                        tuple.push(feature);
+                        tuple.sort_unstable();
+                        let state = tuple_state(&tuple, |i| {
+                            if i == feature {
+                                on
+                            } else {
+                                row[i] == Some(true)
+                            }
+                        });
+                        uncovered.contains(&(tuple, state))
+                    })
+                    .count()
+            };
+            row[feature] = Some(newly_covered(true) > newly_covered(false));
+        }
+
+        let row: Vec<bool> = row.into_iter().map(|on| on == Some(true)).collect();
+        uncovered.retain(|(tuple, state)| tuple_state(tuple, |i| row[i]) != *state);
+        rows.push(row);
+    }
+
+    let mut subsets: Vec<Vec<String>> = Vec::new();
+    for row in rows {
+        let on = row.iter().filter(|&&on| on).count();
+        // Tested elsewhere.
+        if on <= 1 || on == n {
+            continue;
+        }
+        let subset: Vec<String> = features
+            .iter()
+            .zip(&row)
+            .filter(|(_, &on)| on)
+            .map(|(feature, _)| feature.clone())
+            .collect();
+        if !subsets.contains(&subset) {
+            subsets.push(subset);
+        }
+    }
+
+    subsets
+}
+
+/// On/off state of the features in `tuple` as a bitmask, bit `i` being `tuple[i]`.
+fn tuple_state(tuple: &[usize], is_on: impl Fn(usize) -> bool) -> u32 {
+    tuple
+        .iter()
+        .enumerate()
+        .filter(|(_, &feature)| is_on(feature))
+        .fold(0, |acc, (bit, _)| acc | 1 << bit)
+}
+
+/// All `k` element combinations of `items`, in lexicographic order.
+fn combinations(items: &[usize], k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    let mut result = Vec::new();
+    for (i, &item) in items.iter().enumerate() {
+        for mut rest in combinations(&items[i + 1..], k - 1) {
+            rest.insert(0, item);
+            result.push(rest);
+        }
+    }
+    result
+}
+
+/// Where a feature set in the matrix came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FeatureSetKind {
+    /// All features enabled (unconditional).
+    All,
+    /// No features enabled (unconditional).
+    None,
+    /// A single auto-discovered feature.
+    Individual,
+    /// A sampled subset of the auto-discovered features.
+    Subset,
+    /// An exact feature set from the configuration.
+    Exact,
+}
+
+/// A single entry of a package's feature matrix, built and tested by [`test_features`].
+#[derive(Debug, Clone)]
+struct FeatureSet {
+    kind: FeatureSetKind,
+    /// Passed to [`test_features`] as its `feature_selection`.
+    features: Option<Vec<String>>,
+}
+
+impl FeatureSet {
+    fn new(kind: FeatureSetKind, features: Vec<String>) -> Self {
+        Self { kind, features: Some(features) }
+    }
+
+    /// Name of this set's unit in the [`Journal`].
+    ///
+    /// Only depends on the selected features, so the same selection reached through different
+    /// kinds (e.g. an individual feature which is also an exact set) is tested once.
+    fn journal_unit(&self) -> String {
+        match &self.features {
+            None => "all-features".to_owned(),
+            Some(features) => format!("features [{}]", features.join(",")),
+        }
+    }
+}
+
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.kind, &self.features) {
+            (FeatureSetKind::All, _) | (_, None) => write!(f, "all features"),
+            (FeatureSetKind::None, _) => write!(f, "no features"),
+            (FeatureSetKind::Individual, Some(features)) =>
+                write!(f, "individual feature {}", features.join(", ")),
+            (FeatureSetKind::Subset, Some(features)) => write!(f, "feature set {:?}", features),
+            (FeatureSetKind::Exact, Some(features)) =>
+                write!(f, "exact feature set {:?}", features),
+        }
+    }
+}
+
 /// Summary of everything tested for a single package.
 #[derive(Debug, Default)]
 struct PackageSummary {
@@ -167,8 +382,28 @@ struct PackageSummary {
     individual_features: Vec<String>,
     /// Feature subsets which were tested.
     feature_subsets: Vec<Vec<String>>,
-    /// Whether the no-std cross-compilation check was run.
-    no_std_checked: bool,
+    /// Feature sets skipped because they enable the same features as an earlier set, paired
+    /// with that earlier set.
+    equivalent_sets: Vec<(FeatureSet, FeatureSet)>,
+    /// Cross-compilation targets which were built.
+    targets: Vec<String>,
+    /// Tests which only passed on a retry, with their feature set.
+    flaky_tests: Vec<String>,
+    /// Outcome of every unit run so far, in the order they were planned.
+    units: Vec<UnitOutcome>,
+}
+
+impl PackageSummary {
+    /// Record a feature set which passed.
+    fn record(&mut self, set: &FeatureSet) {
+        match (set.kind, &set.features) {
+            (FeatureSetKind::Individual, Some(features)) =>
+                self.individual_features.extend(features.iter().cloned()),
+            (FeatureSetKind::Subset | FeatureSetKind::Exact, Some(features)) =>
+                self.feature_subsets.push(features.clone()),
+            _ => {}
+        }
+    }
 }
 
 impl fmt::Display for PackageSummary {
@@ -193,7 +428,28 @@ impl fmt::Display for PackageSummary {
             ("Examples", fmt_list(&self.examples)),
             ("Individual features", fmt_list(&self.individual_features)),
             ("Feature subsets", fmt_sets(&self.feature_subsets)),
-            ("No-std check", if self.no_std_checked { "ran" } else { "skipped" }.to_string()),
+            (
+                "Equivalent sets skipped",
+                if self.equivalent_sets.is_empty() {
+                    "(none)".to_string()
+                } else {
+                    self.equivalent_sets
+                        .iter()
+                        .map(|(skipped, first)| format!("{} (same as {})", skipped, first))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                },
+            ),
+            ("Targets", fmt_list(&self.targets)),
+            ("Flaky tests", fmt_list(&self.flaky_tests)),
+            (
+                "Resumed units",
+                self.units
+                    .iter()
+                    .filter(|u| matches!(u.status, UnitStatus::Resumed))
+                    .count()
+                    .to_string(),
+            ),
         ];
 
         // Compute the column width from the longest label so values align.
@@ -207,35 +463,289 @@ impl fmt::Display for PackageSummary {
     }
 }
 
-/// Summary of an entire test run, grouped by commit.
+/// Summary of an entire test run, grouped by combination and commit.
 #[derive(Debug, Default)]
 struct TestSummary {
+    combinations: Vec<CombinationSummary>,
+}
+
+/// Summary of the commits tested with a single lockfile and toolchain.
+#[derive(Debug)]
+struct CombinationSummary {
+    combination: Combination,
+    failed: bool,
     // Commit SHA paired with the package summaries tested at that commit.
     commits: Vec<(String, Vec<PackageSummary>)>,
 }
 
 impl TestSummary {
+    /// Returns `true` if the run tested more than one combination, which are then labelled.
+    fn has_several_combinations(&self) -> bool { self.combinations.len() > 1 }
+
+    /// Write a machine-readable report of every unit run so far.
+    fn write_report(&self, target: &ReportTarget) -> Result<(), Box<dyn std::error::Error>> {
+        let several = self.has_several_combinations();
+        let commits: Vec<CommitUnits> = self
+            .combinations
+            .iter()
+            .flat_map(|combination| {
+                let label = several.then(|| combination.combination.to_string());
+                combination.commits.iter().map(move |(sha, packages)| CommitUnits {
+                    commit: sha,
+                    combination: label.clone(),
+                    packages: packages
+                        .iter()
+                        .map(|p| (p.name.as_str(), p.units.as_slice()))
+                        .collect(),
+                })
+            })
+            .collect();
+        report::write(target, &commits)
+    }
+
     /// Print summary to stdout.
     fn print(&self) {
         println!("Test Summary");
-        for (sha, packages) in &self.commits {
-            println!("Commit: {}", sha);
-            for pkg in packages {
-                print!("{}", pkg);
+        for combination in &self.combinations {
+            if self.has_several_combinations() {
+                let status = if combination.failed { "failed" } else { "passed" };
+                println!("Combination: {} ({})", combination.combination, status);
+            }
+            for (sha, packages) in &combination.commits {
+                println!("Commit: {}", sha);
+                for pkg in packages {
+                    print!("{}", pkg);
+                }
+            }
+        }
+    }
+}
+
+/// A cross-compilation target to build for, from `[package.metadata.rbmt.test] targets`.
+#[derive(Debug, Clone, Deserialize)]
+struct TargetConfig {
+    /// Target triple, e.g. `"wasm32-unknown-unknown"`.
+    triple: String,
+    /// Features to enable for the build.
+    #[serde(default)]
+    features: Vec<String>,
+    /// Whether to build with `--no-default-features` (default `true`).
+    #[serde(default = "TargetConfig::default_no_default_features")]
+    no_default_features: bool,
+}
+
+impl TargetConfig {
+    fn default_no_default_features() -> bool { true }
+
+    /// A build for [`NO_STD_TARGET`] with no default features, plus the given features.
+    fn no_std(features: Vec<String>) -> Self {
+        Self { triple: NO_STD_TARGET.to_owned(), features, no_default_features: true }
+    }
+
+    /// Name of this target's unit in the [`Journal`].
+    fn journal_unit(&self) -> String {
+        format!("target {} [{}] {}", self.triple, self.features.join(","), self.no_default_features)
+    }
+}
+
+impl fmt::Display for TargetConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.triple)?;
+        if !self.features.is_empty() {
+            write!(f, " [{}]", self.features.join(", "))?;
+        }
+        if !self.no_default_features {
+            write!(f, " (default features)")?;
+        }
+        Ok(())
+    }
+}
+
+/// An example to run, from `[package.metadata.rbmt.test] examples`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ExampleEntry")]
+struct ExampleConfig {
+    /// Name of the example target.
+    name: String,
+    /// Features to enable, always built with `--no-default-features`.
+    features: Vec<String>,
+    /// Arguments passed to the example.
+    args: Vec<String>,
+    /// File the example's stdout must match, relative to the package directory.
+    stdout: Option<PathBuf>,
+}
+
+/// An example as written in the configuration, either the short string form or a table.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExampleEntry {
+    Short(String),
+    Table {
+        name: String,
+        #[serde(default)]
+        features: Vec<String>,
+        #[serde(default)]
+        args: Vec<String>,
+        stdout: Option<PathBuf>,
+    },
+}
+
+impl TryFrom<ExampleEntry> for ExampleConfig {
+    type Error = String;
+
+    fn try_from(entry: ExampleEntry) -> Result<Self, Self::Error> {
+        match entry {
+            ExampleEntry::Short(example) => {
+                let parts: Vec<&str> = example.split(':').collect();
+                let features = match parts.len() {
+                    1 => Vec::new(),
+                    2 => parts[1].split_whitespace().map(str::to_owned).collect(),
+                    _ =>
+                        return Err(format!(
+                            "Invalid example format: {}, expected 'name' or 'name:features'",
+                            example
+                        )),
+                };
+                Ok(Self { name: parts[0].to_owned(), features, args: Vec::new(), stdout: None })
             }
+            ExampleEntry::Table { name, features, args, stdout } =>
+                Ok(Self { name, features, args, stdout }),
+        }
+    }
+}
+
+impl ExampleConfig {
+    /// Name of this example's unit in the [`Journal`].
+    fn journal_unit(&self) -> String { format!("example {}", self) }
+}
+
+impl fmt::Display for ExampleConfig {
+    /// The short form, e.g. `"bip32:serde rand"`, followed by any arguments.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.features.is_empty() {
+            write!(f, ":{}", self.features.join(" "))?;
+        }
+        if !self.args.is_empty() {
+            write!(f, " -- {}", self.args.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A sanitizer to build and test with, only available on the nightly toolchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Sanitizer {
+    /// `AddressSanitizer`, detects out of bounds accesses and use after free.
+    Address,
+    /// `MemorySanitizer`, detects reads of uninitialized memory.
+    Memory,
+    /// `ThreadSanitizer`, detects data races.
+    Thread,
+}
+
+impl Sanitizer {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Address => "address",
+            Self::Memory => "memory",
+            Self::Thread => "thread",
+        }
+    }
+
+    /// Whether the standard library has to be rebuilt with the sanitizer.
+    ///
+    /// Memory and thread sanitizers report false positives for uninstrumented code.
+    fn needs_build_std(self) -> bool { matches!(self, Self::Memory | Self::Thread) }
+}
+
+/// Sanitizer configuration, read from `[package.metadata.rbmt.sanitizers]` in `Cargo.toml`.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct SanitizersConfig {
+    /// Sanitizers the package is tested with, it is skipped by `test --sanitizer` otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.sanitizers]
+    /// enabled = ["address", "thread"]
+    /// ```
+    enabled: Vec<Sanitizer>,
+}
+
+impl SanitizersConfig {
+    /// Load sanitizer configuration from `[package.metadata.rbmt.sanitizers]` in the package's
+    /// `Cargo.toml`.
+    fn load(crate_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = crate_dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(Self::default());
         }
+        Self::parse(&std::fs::read_to_string(&path)?)
+    }
+
+    /// Parse sanitizer configuration from the contents of a package's `Cargo.toml`.
+    fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(serde::Deserialize, Default)]
+        struct RbmtTable {
+            #[serde(default)]
+            sanitizers: SanitizersConfig,
+        }
+
+        Ok(toml::from_str::<PackageManifest<RbmtTable>>(contents)?.package.metadata.rbmt.sanitizers)
+    }
+}
+
+/// Environment variables which build and test with the given sanitizer.
+///
+/// Everything is built for the host target given explicitly, so the sanitizer flags are not
+/// applied to build scripts and proc macros. Flags already set in the environment are kept.
+fn sanitizer_env(
+    sh: &Shell,
+    sanitizer: Sanitizer,
+) -> Result<Vec<(&'static str, String)>, Box<dyn std::error::Error>> {
+    Ok(compose_sanitizer_env(sanitizer, host_target(sh)?, |var| std::env::var(var).ok()))
+}
+
+/// The environment of [`sanitizer_env`] for the `host` target, given the flags already set in
+/// the environment by `existing`.
+fn compose_sanitizer_env(
+    sanitizer: Sanitizer,
+    host: String,
+    existing: impl Fn(&str) -> Option<String>,
+) -> Vec<(&'static str, String)> {
+    let flag = format!("-Zsanitizer={}", sanitizer.as_str());
+    let with_flag = |var: &str| match existing(var) {
+        Some(flags) if !flags.trim().is_empty() => format!("{} {}", flags, flag),
+        _ => flag.clone(),
+    };
+
+    let mut env = vec![
+        ("RUSTFLAGS", with_flag("RUSTFLAGS")),
+        ("RUSTDOCFLAGS", with_flag("RUSTDOCFLAGS")),
+        ("CARGO_BUILD_TARGET", host),
+    ];
+    if sanitizer.needs_build_std() {
+        // Equivalent to `-Zbuild-std=std`.
+        env.push(("CARGO_UNSTABLE_BUILD_STD", "std".to_owned()));
     }
+    env
 }
 
 /// Test-specific configuration, read from `[package.metadata.rbmt.test]` in `Cargo.toml`.
 #[derive(Debug, Deserialize, Default)]
 #[serde(default)]
-struct TestConfig {
+pub struct TestConfig {
     /// Examples to run with different feature configurations.
     ///
     /// Supported formats:
     /// * `"name"` - runs with no features.
     /// * `"name:feature1 feature2"` - runs with specific features.
+    /// * `{ name = "name", features = [...], args = [...], stdout = "path" }` - runs with
+    ///   specific features and arguments. If `stdout` is set, the example's output must match
+    ///   the contents of that file, which `--bless` rewrites.
     ///
     /// # Examples
     ///
@@ -243,10 +753,11 @@ struct TestConfig {
     /// [package.metadata.rbmt.test]
     /// examples = [
     ///     "bip32",
-    ///     "bip32:serde rand"
+    ///     "bip32:serde rand",
+    ///     { name = "bip32", args = ["--network", "testnet"], stdout = "examples/bip32.stdout" },
     /// ]
     /// ```
-    examples: Vec<String>,
+    examples: Vec<ExampleConfig>,
 
     /// Features to exclude from automatic feature discovery.
     ///
@@ -262,6 +773,8 @@ struct TestConfig {
     /// Options:
     /// * `"log"` - Logarithmic sampling (default) `ceil(log2(n))` random subsets per commit.
     /// * `"all"` - Test all combinations: 2^n - 2 subsets (excluding none, individual, and all).
+    /// * `"pairwise"` - Covering array in which every combination of `pairwise_strength` features
+    ///   appears in each on/off state, deterministic across commits.
     ///
     /// # Examples
     ///
@@ -272,6 +785,17 @@ struct TestConfig {
     #[serde(default)]
     sample_strategy: SampleStrategy,
 
+    /// Strength of the `"pairwise"` sample strategy, either 2 (default) or 3.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.test]
+    /// sample_strategy = "pairwise"
+    /// pairwise_strength = 3
+    /// ```
+    pairwise_strength: Option<usize>,
+
     /// Feature-specific MSRV overrides.
     ///
     /// If a feature is enabled during testing, use this MSRV instead of the default.
@@ -285,29 +809,100 @@ struct TestConfig {
     /// ```
     #[serde(default)]
     msrv_overrides: MsrvOverrides,
+
+    /// Whether to infer MSRV overrides from dependencies, see [`msrv::infer_overrides`].
+    ///
+    /// With `--toolchain msrv`, every feature without an entry in `msrv_overrides` is tested with
+    /// the highest `rust-version` of the dependencies it enables. Defaults to `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.test]
+    /// infer_msrv_overrides = true
+    /// ```
+    infer_msrv_overrides: bool,
+
+    /// Cross-compilation targets to build for, each with its own features.
+    ///
+    /// Replaces the automatic no-std check, which builds for `thumbv7m-none-eabi` with no
+    /// default features if the package declares `#![no_std]`. Each target is added to the
+    /// toolchain with `rustup target add` before it is built.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.test]
+    /// targets = [
+    ///     { triple = "thumbv7m-none-eabi" },
+    ///     { triple = "wasm32-unknown-unknown", features = ["alloc"] },
+    ///     { triple = "i686-unknown-linux-gnu", no_default_features = false },
+    /// ]
+    /// ```
+    targets: Vec<TargetConfig>,
+
+    /// Feature sets which must stay no-std compatible.
+    ///
+    /// Each set is built for `thumbv7m-none-eabi` with no default features, in addition to the
+    /// automatic no-std check, so a feature which pulls in `std` fails to build.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// [package.metadata.rbmt.test]
+    /// no_std_features = [["alloc"], ["alloc", "serde"]]
+    /// ```
+    no_std_features: Vec<Vec<String>>,
 }
 
 impl TestConfig {
     /// Load test configuration from `[package.metadata.rbmt.test]` in the package's `Cargo.toml`.
-    fn load(crate_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn load(crate_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = crate_dir.join("Cargo.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::parse(&std::fs::read_to_string(&path)?)
+    }
+
+    /// Parse test configuration from the contents of a package's `Cargo.toml`.
+    pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
         #[derive(serde::Deserialize, Default)]
         struct RbmtTable {
             #[serde(default)]
             test: TestConfig,
         }
 
-        let path = crate_dir.join("Cargo.toml");
-        if !path.exists() {
-            return Ok(Self::default());
+        let config =
+            toml::from_str::<PackageManifest<RbmtTable>>(contents)?.package.metadata.rbmt.test;
+        if let Some(strength) = config.pairwise_strength.filter(|s| !matches!(s, 2 | 3)) {
+            return Err(
+                format!("Unsupported pairwise_strength {}, expected 2 or 3", strength).into()
+            );
         }
-        let contents = std::fs::read_to_string(&path)?;
-        Ok(toml::from_str::<PackageManifest<RbmtTable>>(&contents)?.package.metadata.rbmt.test)
+        Ok(config)
+    }
+
+    /// Triples of the configured cross-compilation `targets`.
+    pub fn target_triples(&self) -> impl Iterator<Item = String> + '_ {
+        self.targets.iter().map(|target| target.triple.clone())
+    }
+
+    /// The feature combinations configured in `exact_features`.
+    pub fn exact_features(&self) -> &[Vec<String>] { &self.exact_features }
+
+    /// The overrides configured in `msrv_overrides`, by feature.
+    pub fn msrv_overrides(&self) -> BTreeMap<String, String> {
+        self.msrv_overrides.manual.iter().map(|(f, v)| (f.clone(), v.clone())).collect()
     }
 }
 
 /// Build and test with the given features and cargo test arguments.
 ///
-/// If any feature has an MSRV override configured, uses that MSRV instead of the default.
+/// The toolchain is expected to be prepared already, see [`run_feature_sets`]. Failing tests are
+/// re-run up to `retries` times, see [`run_tests`].
+///
+/// Returns the tests which only passed on a retry.
 ///
 /// # Arguments
 ///
@@ -315,200 +910,778 @@ impl TestConfig {
 ///   with no features, `Some(["feat1", ...])` means `--no-default-features --features feat1 ...`
 fn test_features(
     sh: &Shell,
-    toolchain: Toolchain,
     feature_selection: Option<&[String]>,
     cargo_args: &[String],
-    msrv_overrides: &MsrvOverrides,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Check for MSRV override.
-    let msrv_override = msrv_overrides.get(feature_selection)?;
-    prepare_toolchain_with_override(sh, toolchain, msrv_override)?;
-
-    match feature_selection {
-        None => {
-            // Test all features.
-            cargo_cmd(sh).arg("build").arg("--all-features").args(cargo_args).run_with_capture()?;
-            cargo_cmd(sh).arg("test").arg("--all-features").args(cargo_args).run_with_capture()?;
-        }
-        Some(features) => {
-            // Test specific features (or no features if empty).
-            let mut build_cmd = cargo_cmd(sh).arg("build").arg("--no-default-features");
-            if !features.is_empty() {
-                // Avoid issues with feature names which contain a hyphen.
-                build_cmd = build_cmd.arg("--features").arg(features.join(","));
-            }
-            build_cmd.args(cargo_args).run_with_capture()?;
+    retries: usize,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut args = feature_args(feature_selection);
+    args.extend(cargo_args.iter().cloned());
 
-            let mut test_cmd = cargo_cmd(sh).arg("test").arg("--no-default-features");
-            if !features.is_empty() {
-                // Avoid issues with feature names which contain a hyphen.
-                test_cmd = test_cmd.arg("--features").arg(features.join(","));
-            }
-            test_cmd.args(cargo_args).run_with_capture()?;
-        }
-    }
-
-    Ok(())
+    cargo_cmd(sh).arg("build").args(&args).run_with_capture()?;
+    run_tests(sh, &args, retries)
 }
 
-/// Run build and test for all crates with the specified toolchain.
-///
-/// If `baseline` is `Some`, checks out each commit between `baseline` and HEAD in turn,
-/// running the full test suite at each one. The checkout is restored via
-/// [`git::GitSwitchGuard`] even on failure, and the run stops immediately if any commit fails.
+/// Run `cargo test` with the given arguments, re-running only the failing tests up to `retries`
+/// times.
 ///
-/// # Arguments
-///
-/// * `sh` - The shell environment.
-/// * `lockfile` - Which lockfile variant to use.
-/// * `toolchain` - Which toolchain to use.
-/// * `baseline` - Optional baseline ref for testing multiple commits.
-/// * `packages` - Packages to test (empty = all).
-/// * `cargo_args` - Additional arguments to pass to cargo build and test commands.
-pub fn run(
+/// Returns the tests which failed at first but passed on a retry, always empty if `retries` is
+/// zero. Fails if the tests still fail after the last retry, or if the failing tests cannot be
+/// identified (e.g. the build failed).
+fn run_tests(
     sh: &Shell,
-    lockfile: LockFile,
-    toolchain: Toolchain,
-    baseline: Option<&str>,
-    packages: &[String],
-    cargo_args: &[String],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut progress = ProgressGuard::new();
-    let mut summary = TestSummary::default();
+    args: &[String],
+    retries: usize,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if retries == 0 {
+        cargo_cmd(sh).arg("test").args(args).run_with_capture()?;
+        return Ok(Vec::new());
+    }
 
-    git::for_each_commit(sh, lockfile, baseline, |sh| {
-        // Resolve packages for each commit, so we only test packages that exist in that commit.
-        let resolved_packages = get_workspace_packages(sh, packages)?;
-        let sha = git::current_commit_id(sh).unwrap_or_else(|| "unknown".to_owned());
-        let pkg_summaries = test_commit(sh, toolchain, &resolved_packages, cargo_args)?;
-        summary.commits.push((sha, pkg_summaries));
-        Ok(())
-    })?;
+    // Run every target, so a failure in one does not hide the failures of the rest.
+    let Err(mut error) =
+        cargo_cmd(sh).arg("test").arg("--no-fail-fast").args(args).run_with_full_capture()
+    else {
+        return Ok(Vec::new());
+    };
+    let Some(first) = FailedTests::from_error(error.as_ref()) else {
+        return Err(error);
+    };
 
-    rbmt_eprintln!("Tests complete.");
-    progress.disable();
-    summary.print();
-    Ok(())
+    let mut failed = first.clone();
+    for attempt in 1..=retries {
+        rbmt_eprintln!("Retrying {} (attempt {} of {})", failed, attempt, retries);
+        match failed.retry(sh, args) {
+            Ok(()) => return Ok(first.names()),
+            Err(e) => {
+                failed = match FailedTests::from_error(e.as_ref()) {
+                    Some(failed) => failed,
+                    None => return Err(e),
+                };
+                error = e;
+            }
+        }
+    }
+    Err(error)
 }
 
-/// Run the full test suite at the current commit and return the per-package summaries.
-fn test_commit(
-    sh: &Shell,
-    toolchain: Toolchain,
-    packages: &[Package],
-    cargo_args: &[String],
-) -> Result<Vec<PackageSummary>, Box<dyn std::error::Error>> {
-    rbmt_eprintln!("Testing {} crate(s)", packages.len());
+/// Tests which failed in a `cargo test` run, parsed from its output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FailedTests {
+    /// Cargo arguments which select each failed target, e.g. `--lib` or `--test foo`.
+    targets: Vec<String>,
+    /// Names of the failed tests, empty if they are unknown because a target failed without
+    /// listing them (e.g. the test binary crashed).
+    tests: Vec<String>,
+}
 
-    let mut pkg_summaries = Vec::new();
+impl FailedTests {
+    /// Parse the failures from the output of `cargo test`, `None` if no target failed to test.
+    fn parse(stdout: &str, stderr: &str) -> Option<Self> {
+        // Cargo reports each failed target as "error: test failed, to rerun pass `--lib`".
+        let mut targets: Vec<String> = Vec::new();
+        for line in stderr.lines() {
+            let Some((_, rest)) = line.split_once("to rerun pass `") else { continue };
+            if let Some((target, _)) = rest.split_once('`') {
+                if !targets.iter().any(|t| t == target) {
+                    targets.push(target.to_owned());
+                }
+            }
+        }
+        if targets.is_empty() {
+            return None;
+        }
 
-    for package in packages {
-        rbmt_eprintln!("Testing package: {}", package.name);
+        // The test harness lists the failed tests, indented, right before its result line.
+        let mut tests = Vec::new();
+        let mut results = 0;
+        let mut listed: Option<Vec<String>> = None;
+        for line in stdout.lines() {
+            if line == "failures:" {
+                listed = Some(Vec::new());
+            } else if line.starts_with("test result: FAILED") {
+                results += 1;
+                tests.extend(listed.take().unwrap_or_default());
+            } else if let Some(name) = line.strip_prefix("    ") {
+                if let Some(listed) = &mut listed {
+                    listed.push(name.to_owned());
+                }
+            } else if !line.is_empty() {
+                listed = None;
+            }
+        }
+        // A target which crashed has no result line, so some of the failed tests are unknown.
+        if results != targets.len() {
+            tests.clear();
+        }
+
+        Some(Self { targets, tests })
+    }
+
+    /// The failures of a `cargo test` command run with [`CmdExt::run_with_full_capture`].
+    fn from_error(e: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        let output = e.downcast_ref::<CommandError>()?.output.as_ref()?;
+        Self::parse(&output.stdout, &output.stderr)
+    }
+
+    /// The failed tests, or the failed targets if the tests are unknown.
+    fn names(&self) -> Vec<String> {
+        if self.tests.is_empty() {
+            self.targets.clone()
+        } else {
+            self.tests.clone()
+        }
+    }
+
+    /// Re-run the failed tests, or the whole of the failed targets if the tests are unknown.
+    fn retry(&self, sh: &Shell, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut cmd = cargo_cmd(sh).arg("test").arg("--no-fail-fast").args(args);
+        if self.tests.is_empty() {
+            // Doc tests cannot be selected together with other targets, so run everything.
+            let doc = self.targets.iter().any(|t| t.split_whitespace().any(|arg| arg == "--doc"));
+            if !doc || self.targets.len() == 1 {
+                cmd = cmd.args(self.targets.iter().flat_map(|t| t.split_whitespace()));
+            }
+        } else {
+            // Other targets are run too, but the filters leave nothing to run in them.
+            if !args.iter().any(|arg| arg == "--") {
+                cmd = cmd.arg("--");
+            }
+            // Doc tests are not matched by `--exact`, so they are re-run by substring along with
+            // any other test which happens to contain the name.
+            if !self.tests.iter().any(|test| test.ends_with(')') && test.contains(" (line ")) {
+                cmd = cmd.arg("--exact");
+            }
+            cmd = cmd.args(&self.tests);
+        }
+        cmd.run_with_full_capture()
+    }
+}
+
+impl fmt::Display for FailedTests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tests.is_empty() {
+            write!(f, "failed target(s) {}", self.targets.join(", "))
+        } else {
+            write!(f, "failed test(s) {}", self.tests.join(", "))
+        }
+    }
+}
+
+/// Options for a test run, see [`run`].
+pub struct TestOptions {
+    /// Lockfile and toolchain combinations to test with, in order.
+    pub combinations: Vec<Combination>,
+    /// Optional baseline ref for testing multiple commits.
+    pub baseline: Option<String>,
+    /// Only test packages changed since this ref, plus the packages which depend on them.
+    pub changed_since: Option<String>,
+    /// Number of feature sets to build and test concurrently.
+    pub jobs: NonZeroUsize,
+    /// Skip units which passed in a previous run with the same lockfile and toolchain.
+    pub resume: bool,
+    /// Write a machine-readable report of every unit, even if the run fails.
+    pub report: Option<ReportTarget>,
+    /// Build and test the packages which opt in with this sanitizer, requires nightly.
+    pub sanitizer: Option<Sanitizer>,
+    /// Number of times to re-run failing tests before failing the feature set.
+    pub retries: usize,
+    /// Fail the feature set if a test only passed on a retry.
+    pub strict_flaky: bool,
+    /// Rewrite the expected output files of examples instead of comparing against them.
+    pub bless: bool,
+    /// Only test this shard's share of the units.
+    pub shard: Option<Shard>,
+    /// JSON report of an earlier run, whose durations balance the shards.
+    pub shard_durations: Option<PathBuf>,
+    /// Additional arguments to pass to cargo build and test commands.
+    pub cargo_args: Vec<String>,
+}
+
+/// State shared by everything tested at a single commit.
+struct CommitContext<'a> {
+    options: &'a TestOptions,
+    toolchain: Toolchain,
+    journal: &'a Journal,
+    /// Environment of the sanitizer, empty if not running with one.
+    sanitizer_env: &'a [(&'static str, String)],
+    /// Prefix of each package's units in the journal, missing if its contents cannot be
+    /// identified.
+    unit_prefixes: HashMap<String, String>,
+    /// Journal units of each package in this shard, `None` if not sharding.
+    shard_units: Option<HashMap<String, HashSet<String>>>,
+}
+
+impl<'a> CommitContext<'a> {
+    /// Identify the current commit's units of each package by the contents it is built from,
+    /// the lockfile, and the toolchain.
+    ///
+    /// A package is built from its own directory, those of the workspace packages it depends on,
+    /// and the workspace manifest, so a change elsewhere does not re-test it.
+    fn new(
+        sh: &Shell,
+        options: &'a TestOptions,
+        toolchain: Toolchain,
+        journal: &'a Journal,
+        sanitizer_env: &'a [(&'static str, String)],
+        toolchain_id: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = get_workspace_root(sh)?;
+        let lock = std::fs::read_to_string(root.join("Cargo.lock")).unwrap_or_default();
+        let fingerprint = journal::fingerprint(&[
+            &lock,
+            toolchain_id,
+            options.sanitizer.map_or("", Sanitizer::as_str),
+            &options.cargo_args.join(" "),
+        ]);
+
+        let packages = get_workspace_packages(sh, &[])?;
+        let mut unit_prefixes = HashMap::new();
+        for package in &packages {
+            let mut paths = vec![root.join("Cargo.toml")];
+            paths.extend(
+                tree::with_dependencies(&packages, &package.name).iter().map(|p| p.dir.clone()),
+            );
+            if let Some(content) = git::content_id(sh, &paths) {
+                unit_prefixes.insert(package.name.clone(), format!("{}\t{}", content, fingerprint));
+            }
+        }
+        Ok(Self { options, toolchain, journal, sanitizer_env, unit_prefixes, shard_units: None })
+    }
+
+    /// Returns `true` if the package has any units in this shard.
+    fn has_shard_units(&self, package: &str) -> bool {
+        self.shard_units.as_ref().is_none_or(|units| units.contains_key(package))
+    }
+
+    /// Returns `true` if the unit belongs to this shard.
+    fn in_shard(&self, package: &str, unit: &str) -> bool {
+        self.shard_units
+            .as_ref()
+            .is_none_or(|units| units.get(package).is_some_and(|units| units.contains(unit)))
+    }
+
+    fn unit_key(&self, package: &str, unit: &str) -> Option<String> {
+        self.unit_prefixes.get(package).map(|prefix| format!("{}\t{}\t{}", prefix, package, unit))
+    }
+
+    /// Returns `true` if resuming and the unit passed in a previous run.
+    fn is_done(&self, package: &str, unit: &str) -> bool {
+        self.options.resume
+            && self.unit_key(package, unit).is_some_and(|key| self.journal.contains(&key))
+    }
+
+    /// Record a unit which passed.
+    fn mark_done(&self, package: &str, unit: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.unit_key(package, unit) {
+            Some(key) => self.journal.record(&key),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Run build and test for all crates with each lockfile and toolchain combination.
+///
+/// If `baseline` is `Some`, checks out each commit between `baseline` and HEAD in turn,
+/// running the full test suite at each one. The checkout is restored via
+/// [`git::GitSwitchGuard`] even on failure, and a combination stops immediately if any commit
+/// fails. The remaining combinations are still tested, and the run fails at the end.
+///
+/// Every unit which passes (example, feature set, or target build of a package at a commit) is
+/// recorded in a journal under `<target-dir>/rbmt/`. With `resume`, units already recorded for
+/// the same commit contents, lockfile, toolchain, sanitizer, and cargo arguments are skipped.
+///
+/// With `changed_since`, only packages with changes since that ref and the packages which depend
+/// on them, including through dev-dependencies, are tested.
+///
+/// With a `sanitizer`, only packages which enable it in `[package.metadata.rbmt.sanitizers]` are
+/// tested, for the host target and without the cross-compilation builds.
+///
+/// # Arguments
+///
+/// * `sh` - The shell environment.
+/// * `packages` - Packages to test (empty = all).
+/// * `options` - Lockfiles, toolchains, and the rest of the run's configuration.
+pub fn run(
+    sh: &Shell,
+    packages: &[String],
+    options: &TestOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut progress = ProgressGuard::new();
+    let mut summary = TestSummary::default();
+
+    if let Some(sanitizer) = options.sanitizer {
+        if options.combinations.iter().any(|c| c.toolchain != Toolchain::Nightly) {
+            return Err(format!(
+                "The {} sanitizer requires the nightly toolchain, use --toolchain nightly",
+                sanitizer.as_str()
+            )
+            .into());
+        }
+    }
+
+    let journal_path = get_target_dir(sh)?.join("rbmt").join("test-journal");
+    // A plan must not forget the units a later `--resume` would skip.
+    let journal = if plan::is_active() {
+        Journal::read_only(&journal_path, options.resume)?
+    } else {
+        Journal::open(&journal_path, options.resume)?
+    };
+
+    let durations = match &options.shard_durations {
+        Some(path) => report::read_durations(path)?,
+        None => HashMap::new(),
+    };
+
+    let mut errors = Vec::new();
+    for &combination in &options.combinations {
+        if options.combinations.len() > 1 {
+            rbmt_eprintln!("Testing with {}", combination);
+        }
+        // A fresh shell, so the toolchain pinned for one combination does not leak into the next.
+        let combination_sh = Shell::new()?;
+        combination_sh.change_dir(sh.current_dir());
+
+        summary.combinations.push(CombinationSummary {
+            combination,
+            failed: false,
+            commits: Vec::new(),
+        });
+        let combination_summary = summary.combinations.last_mut().expect("combination just pushed");
+        let result = test_combination(
+            &combination_sh,
+            packages,
+            options,
+            combination,
+            &journal,
+            &durations,
+            combination_summary,
+        );
+        if let Err(e) = result {
+            combination_summary.failed = true;
+            errors.push((combination, e));
+        }
+    }
+    // Nothing was tested, so there is nothing to report.
+    if plan::is_active() {
+        return errors.into_iter().next().map_or(Ok(()), |(_, e)| Err(e));
+    }
+
+    if let Some(target) = &options.report {
+        match summary.write_report(target) {
+            Ok(()) => rbmt_eprintln!("Wrote test report to {}", target.path.display()),
+            // Do not let a broken report hide the test failure.
+            Err(e) if !errors.is_empty() =>
+                eprintln!("Warning: Failed to write test report: {}", e),
+            Err(e) => return Err(e),
+        }
+    }
+    if !summary.has_several_combinations() {
+        if let Some((_, e)) = errors.pop() {
+            return Err(e);
+        }
+    }
+
+    rbmt_eprintln!("Tests complete.");
+    progress.disable();
+    summary.print();
+    if !errors.is_empty() {
+        let failures: Vec<String> =
+            errors.iter().map(|(combination, e)| format!("{}: {}", combination, e)).collect();
+        return Err(format!("Tests failed with:\n  {}", failures.join("\n  ")).into());
+    }
+    Ok(())
+}
+
+/// Test every commit with a single lockfile and toolchain, filling in `summary` as it goes.
+fn test_combination(
+    sh: &Shell,
+    packages: &[String],
+    options: &TestOptions,
+    combination: Combination,
+    journal: &Journal,
+    durations: &report::Durations,
+    summary: &mut CombinationSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Identify the toolchain before any commit pins one in the shell's environment.
+    let Combination { lockfile, toolchain } = combination;
+    let toolchain_id = format!("{:?} {}", toolchain, rbmt_cmd!(sh, "rustc --version").read()?);
+
+    let sanitizer_env = match options.sanitizer {
+        Some(sanitizer) => sanitizer_env(sh, sanitizer)?,
+        None => Vec::new(),
+    };
+    for (key, value) in &sanitizer_env {
+        sh.set_var(key, value);
+    }
+
+    git::for_each_commit(sh, lockfile, options.baseline.as_deref(), |sh| {
+        // Resolve packages for each commit, so we only test packages that exist in that commit.
+        let mut resolved_packages = get_workspace_packages(sh, packages)?;
+        if let Some(since) = &options.changed_since {
+            let affected = tree::changed_with_dependents(sh, since)?;
+            resolved_packages.retain(|p| affected.contains(&p.name));
+            rbmt_eprintln!(
+                "{} package(s) changed since {} or depend on a changed package",
+                resolved_packages.len(),
+                since
+            );
+        }
+        let sha = git::current_commit_id(sh).unwrap_or_else(|| "unknown".to_owned());
+        let mut ctx =
+            CommitContext::new(sh, options, toolchain, journal, &sanitizer_env, &toolchain_id)?;
+        if let Some(shard) = options.shard {
+            ctx.shard_units = Some(plan_shard(sh, &resolved_packages, options, shard, durations)?);
+        }
+        summary.commits.push((sha, Vec::new()));
+        // Summaries are filled in as the commit is tested, so a failure is still reported.
+        let pkg_summaries = &mut summary.commits.last_mut().expect("commit just pushed").1;
+        test_commit(sh, &resolved_packages, &ctx, pkg_summaries)
+    })
+}
+
+/// Run the full test suite at the current commit, adding a summary per package.
+fn test_commit(
+    sh: &Shell,
+    packages: &[Package],
+    ctx: &CommitContext,
+    pkg_summaries: &mut Vec<PackageSummary>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    rbmt_eprintln!("Testing {} crate(s)", packages.len());
+    // Only the MSRV toolchain is overridden, for the packages which opt in to inference.
+    let mut inferred_overrides = HashMap::new();
+    if ctx.toolchain == Toolchain::Msrv {
+        let mut inferring = Vec::new();
+        for package in packages {
+            if TestConfig::load(&package.dir)?.infer_msrv_overrides {
+                inferring.push(package.clone());
+            }
+        }
+        if !inferring.is_empty() {
+            inferred_overrides = msrv::infer_overrides(sh, &inferring)?;
+        }
+    }
+
+    for package in packages {
+        if !ctx.has_shard_units(&package.name) {
+            rbmt_eprintln!("No units of {} in this shard, skipping", package.name);
+            continue;
+        }
+        if let Some(sanitizer) = ctx.options.sanitizer {
+            if !SanitizersConfig::load(&package.dir)?.enabled.contains(&sanitizer) {
+                rbmt_eprintln!(
+                    "The {} sanitizer is not enabled for {}, skipping",
+                    sanitizer.as_str(),
+                    package.name
+                );
+                continue;
+            }
+        }
+        rbmt_eprintln!("Testing package: {}", package.name);
 
         let _dir = sh.push_dir(&package.dir);
-        let config = TestConfig::load(&package.dir)?;
+        let mut config = TestConfig::load(&package.dir)?;
+        if let Some(inferred) = inferred_overrides.remove(&package.name) {
+            if !inferred.is_empty() {
+                rbmt_eprintln!("Inferred MSRV overrides for {}: {:?}", package.name, inferred);
+            }
+            config.msrv_overrides.inferred = inferred;
+        }
+
+        pkg_summaries.push(PackageSummary { name: package.name.clone(), ..Default::default() });
+        let pkg_summary = pkg_summaries.last_mut().expect("package just pushed");
+
+        do_examples(sh, ctx, &config, pkg_summary)?;
+        do_feature_matrix(sh, ctx, package, &config, pkg_summary)?;
+        // Sanitizers are only supported for the host target.
+        if ctx.options.sanitizer.is_none() {
+            do_target_builds(sh, ctx, &package.dir, &config, pkg_summary)?;
+        }
+    }
 
-        let mut pkg_summary = PackageSummary { name: package.name.clone(), ..Default::default() };
+    Ok(())
+}
 
-        do_examples(sh, toolchain, &config, &mut pkg_summary)?;
-        do_feature_matrix(sh, toolchain, package, &config, cargo_args, &mut pkg_summary)?;
-        do_no_std_check(sh, &package.dir, &mut pkg_summary)?;
+/// Split the units of every package at the current commit across the shards, returning the
+/// journal units of each package in `shard`.
+///
+/// Every shard plans the same units in the same order, so they agree on the split. Units are
+/// weighed by their duration in `durations`, see [`Shard::select`].
+fn plan_shard(
+    sh: &Shell,
+    packages: &[Package],
+    options: &TestOptions,
+    shard: Shard,
+    durations: &report::Durations,
+) -> Result<HashMap<String, HashSet<String>>, Box<dyn std::error::Error>> {
+    // Package name, journal unit, and the duration of the unit if known.
+    let mut units: Vec<(&str, String, Option<f64>)> = Vec::new();
+    for package in packages {
+        if let Some(sanitizer) = options.sanitizer {
+            if !SanitizersConfig::load(&package.dir)?.enabled.contains(&sanitizer) {
+                continue;
+            }
+        }
+        let config = TestConfig::load(&package.dir)?;
+        let mut push = |unit: String, kind: UnitKind, name: String| {
+            let key = (package.name.clone(), kind.as_str().to_owned(), name);
+            units.push((&package.name, unit, durations.get(&key).copied()));
+        };
 
-        pkg_summaries.push(pkg_summary);
+        for example in &config.examples {
+            push(example.journal_unit(), UnitKind::Example, example.to_string());
+        }
+        let sets = plan_feature_matrix(package, &config, git::current_commit_id(sh));
+        let mut summary = PackageSummary { name: package.name.clone(), ..Default::default() };
+        for set in skip_equivalent_sets(&package.feature_graph, sets, &mut summary) {
+            push(set.journal_unit(), UnitKind::FeatureSet, set.to_string());
+        }
+        if options.sanitizer.is_none() {
+            for target in plan_targets(sh, &package.dir, &config, &package.name)? {
+                push(target.journal_unit(), UnitKind::Target, target.to_string());
+            }
+        }
     }
 
-    Ok(pkg_summaries)
+    let weights: Vec<Option<f64>> = units.iter().map(|(_, _, duration)| *duration).collect();
+    let mut shard_units: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut count = 0;
+    for ((package, unit, _), selected) in units.iter().zip(shard.select(&weights)) {
+        if selected {
+            shard_units.entry((*package).to_owned()).or_default().insert(unit.clone());
+            count += 1;
+        }
+    }
+    rbmt_eprintln!("Shard {} has {} of {} unit(s)", shard, count, units.len());
+    Ok(shard_units)
 }
 
 /// Run examples.
 fn do_examples(
     sh: &Shell,
-    toolchain: Toolchain,
+    ctx: &CommitContext,
     config: &TestConfig,
     summary: &mut PackageSummary,
 ) -> Result<(), Box<dyn std::error::Error>> {
     rbmt_eprintln!("Running examples in {}", summary.name);
 
     for example in &config.examples {
-        let parts: Vec<&str> = example.split(':').collect();
-
-        match parts.len() {
-            1 => {
-                let name = parts[0];
-                rbmt_eprintln!("Running example {} with no features in {}", name, summary.name);
-                prepare_toolchain_with_override(sh, toolchain, None)?;
-
-                cargo_cmd(sh)
-                    .arg("run")
-                    .arg("--no-default-features")
-                    .arg("--example")
-                    .arg(name)
-                    .run_with_capture()?;
-            }
-            2 => {
-                let name = parts[0];
-                let features: Vec<String> =
-                    parts[1].split_whitespace().map(std::string::ToString::to_string).collect();
+        let label = example.to_string();
+        let unit = example.journal_unit();
+        if !ctx.in_shard(&summary.name, &unit) {
+            continue;
+        }
+        // Blessing always runs, so every expected output file is rewritten.
+        if !ctx.options.bless && ctx.is_done(&summary.name, &unit) {
+            rbmt_eprintln!("Skipping example {} in {}, passed previously", label, summary.name);
+            summary.units.push(UnitOutcome::resumed(UnitKind::Example, label.clone()));
+            summary.examples.push(label);
+            continue;
+        }
 
-                rbmt_eprintln!(
-                    "Running example {} with features {:?} in {}",
-                    name,
-                    features,
-                    summary.name
-                );
+        let start = Instant::now();
+        let result = run_example(sh, ctx, config, example, &summary.name)
+            .and_then(|()| ctx.mark_done(&summary.name, &unit));
+        summary.units.push(UnitOutcome::finished(UnitKind::Example, label.clone(), start, &result));
+        result?;
 
-                // Prepare toolchain with any MSRV override for these features.
-                let msrv_override = config.msrv_overrides.get(Some(&features))?;
-                prepare_toolchain_with_override(sh, toolchain, msrv_override)?;
+        summary.examples.push(label);
+    }
 
-                cargo_cmd(sh)
-                    .arg("run")
-                    .arg("--no-default-features")
-                    .arg("--example")
-                    .arg(name)
-                    .arg("--features")
-                    // Avoid issues with feature names which contain a hyphen.
-                    .arg(features.join(","))
-                    .run_with_capture()?;
-            }
-            _ => {
-                return Err(format!(
-                    "Invalid example format: {}, expected 'name' or 'name:features'",
-                    example
-                )
-                .into());
-            }
-        }
+    Ok(())
+}
 
-        summary.examples.push(example.clone());
+/// Run a single example, comparing its stdout against the expected output file if configured.
+///
+/// With `bless`, the expected output file is rewritten instead.
+fn run_example(
+    sh: &Shell,
+    ctx: &CommitContext,
+    config: &TestConfig,
+    example: &ExampleConfig,
+    package_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd =
+        cargo_cmd(sh).arg("run").arg("--no-default-features").arg("--example").arg(&example.name);
+    if example.features.is_empty() {
+        rbmt_eprintln!("Running example {} with no features in {}", example.name, package_name);
+        prepare_toolchain_with_override(sh, ctx.toolchain, None)?;
+    } else {
+        rbmt_eprintln!(
+            "Running example {} with features {:?} in {}",
+            example.name,
+            example.features,
+            package_name
+        );
+
+        // Prepare toolchain with any MSRV override for these features.
+        let msrv_override = config.msrv_overrides.get(Some(&example.features))?;
+        prepare_toolchain_with_override(sh, ctx.toolchain, msrv_override)?;
+
+        // Avoid issues with feature names which contain a hyphen.
+        cmd = cmd.arg("--features").arg(example.features.join(","));
+    }
+    if !example.args.is_empty() {
+        cmd = cmd.arg("--").args(&example.args);
     }
 
+    let Some(expected_path) = &example.stdout else {
+        return cmd.run_with_capture();
+    };
+    let expected_path = sh.current_dir().join(expected_path);
+    let stdout = cmd.read_with_capture()?;
+    if plan::is_active() {
+        return Ok(());
+    }
+
+    if ctx.options.bless {
+        if let Some(parent) = expected_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&expected_path, &stdout)?;
+        rbmt_eprintln!(
+            "Wrote expected output of example {} to {}",
+            example,
+            expected_path.display()
+        );
+        return Ok(());
+    }
+
+    let expected = std::fs::read_to_string(&expected_path).map_err(|e| {
+        format!(
+            "Failed to read expected output {}: {}, run with --bless to create it",
+            expected_path.display(),
+            e
+        )
+    })?;
+    // Files checked out on Windows may have CRLF line endings.
+    let expected = expected.replace("\r\n", "\n");
+    if expected != stdout {
+        return Err(format!(
+            "Output of example {} differs from {}, run with --bless to accept it:\n{}",
+            example,
+            expected_path.display(),
+            line_diff(&expected, &stdout)
+        )
+        .into());
+    }
     Ok(())
 }
 
+/// A minimal line diff of `expected` and `actual`, with `-` for missing and `+` for extra lines.
+///
+/// Only the changed lines are included, each prefixed with its line number in `expected` or
+/// `actual` respectively.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Longest common subsequence lengths of every pair of suffixes.
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || (i < expected.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            let _ = writeln!(diff, "{:>4} - {}", i + 1, expected[i]);
+            i += 1;
+        } else {
+            let _ = writeln!(diff, "{:>4} + {}", j + 1, actual[j]);
+            j += 1;
+        }
+    }
+    if diff.is_empty() {
+        // Same lines, so the difference is in the line endings.
+        diff.push_str("(differs in trailing newlines)\n");
+    }
+    diff
+}
+
 /// Run feature matrix tests.
 ///
 /// 1. All features (unconditional)
 /// 2. No features (unconditional)
 /// 3. Auto-discovered features individually + subsets per commit (unconditional)
 /// 4. Exact feature sets (when configured)
+///
+/// Sets which enable exactly the same features as an earlier set, once resolved through the
+/// package's `[features]` table, are skipped.
+///
+/// The whole matrix is planned up front so that it can be split across `jobs` workers, and so
+/// sets which passed in a previous run can be dropped. The summary is recorded in plan order
+/// regardless of the order in which the sets finish.
 fn do_feature_matrix(
     sh: &Shell,
-    toolchain: Toolchain,
+    ctx: &CommitContext,
     package: &Package,
     config: &TestConfig,
-    cargo_args: &[String],
     summary: &mut PackageSummary,
 ) -> Result<(), Box<dyn std::error::Error>> {
     rbmt_eprintln!("Running feature matrix tests in {}", package.name);
 
-    // Test all features.
-    rbmt_eprintln!("Testing all features in {}", package.name);
-    test_features(sh, toolchain, None, cargo_args, &config.msrv_overrides)?;
+    let sets = plan_feature_matrix(package, config, git::current_commit_id(sh));
+    let mut sets = skip_equivalent_sets(&package.feature_graph, sets, summary);
+    sets.retain(|set| ctx.in_shard(&package.name, &set.journal_unit()));
+    run_feature_sets(sh, ctx, &sets, &config.msrv_overrides, summary)?;
+
+    for set in &sets {
+        summary.record(set);
+    }
+
+    Ok(())
+}
+
+/// Feature selections of the package's matrix, as tested by `test` at `commit`.
+///
+/// The `commit` seeds the log sampling strategy, so passing the same one at another ref samples
+/// the same subsets. Each selection is `None` for `--all-features`, otherwise the features to
+/// enable with `--no-default-features`. Equivalent sets are already dropped.
+pub fn feature_matrix(
+    package: &Package,
+    commit: Option<String>,
+) -> Result<Vec<Option<Vec<String>>>, Box<dyn std::error::Error>> {
+    let config = TestConfig::load(&package.dir)?;
+    let sets = plan_feature_matrix(package, &config, commit);
+    let mut summary = PackageSummary { name: package.name.clone(), ..Default::default() };
+    let sets = skip_equivalent_sets(&package.feature_graph, sets, &mut summary);
+    Ok(sets.into_iter().map(|set| set.features).collect())
+}
+
+/// Cargo arguments selecting the features of a [`feature_matrix`] entry.
+pub fn feature_args(features: Option<&[String]>) -> Vec<String> {
+    match features {
+        None => vec!["--all-features".to_owned()],
+        Some([]) => vec!["--no-default-features".to_owned()],
+        Some(features) => vec![
+            "--no-default-features".to_owned(),
+            "--features".to_owned(),
+            // Avoid issues with feature names which contain a hyphen.
+            features.join(","),
+        ],
+    }
+}
 
-    // Test no features.
-    rbmt_eprintln!("Testing no features in {}", package.name);
-    test_features(sh, toolchain, Some(&[]), cargo_args, &config.msrv_overrides)?;
+/// Plan every feature set of the package's matrix, see [`do_feature_matrix`].
+fn plan_feature_matrix(
+    package: &Package,
+    config: &TestConfig,
+    commit: Option<String>,
+) -> Vec<FeatureSet> {
+    let mut sets = vec![
+        FeatureSet { kind: FeatureSetKind::All, features: None },
+        FeatureSet::new(FeatureSetKind::None, Vec::new()),
+    ];
 
     // Test each discovered feature in isolation, plus subsets.
     let features: Vec<String> = package
@@ -524,67 +1697,351 @@ fn do_feature_matrix(
             package.name,
             features
         );
-        discovered_feature_matrix(
-            sh,
-            toolchain,
-            &features,
-            config.sample_strategy,
-            cargo_args,
-            &config.msrv_overrides,
-            summary,
-        )?;
+        sets.extend(discovered_feature_matrix(&features, config, commit));
     }
 
     // Test exact feature sets.
-    for features in &config.exact_features {
-        rbmt_eprintln!("Testing exact feature set in {}: {:?}", package.name, features);
-        test_features(
-            sh,
-            toolchain,
-            Some(features.as_slice()),
-            cargo_args,
-            &config.msrv_overrides,
-        )?;
-        summary.feature_subsets.push(features.clone());
+    sets.extend(
+        config.exact_features.iter().map(|f| FeatureSet::new(FeatureSetKind::Exact, f.clone())),
+    );
+
+    sets
+}
+
+/// Drop sets which enable the same features as an earlier set, recording them in the summary.
+fn skip_equivalent_sets(
+    graph: &BTreeMap<String, Vec<String>>,
+    sets: Vec<FeatureSet>,
+    summary: &mut PackageSummary,
+) -> Vec<FeatureSet> {
+    let mut seen: HashMap<BTreeSet<String>, usize> = HashMap::new();
+    let mut kept: Vec<FeatureSet> = Vec::new();
+
+    for set in sets {
+        let resolved = resolve_features(graph, set.features.as_deref());
+        if let Some(&first) = seen.get(&resolved) {
+            rbmt_eprintln!(
+                "Skipping {} in {}, it enables the same features as {}",
+                set,
+                summary.name,
+                kept[first]
+            );
+            summary.equivalent_sets.push((set, kept[first].clone()));
+        } else {
+            seen.insert(resolved, kept.len());
+            kept.push(set);
+        }
     }
 
-    Ok(())
+    kept
+}
+
+/// Resolve everything enabled by building with `--no-default-features` and the given features.
+///
+/// Follows the package's `[features]` table, so the result contains every enabled feature, plus
+/// enabled optional dependencies (`dep:name`) and dependency features (`name/feature`). A weak
+/// dependency feature (`name?/feature`) is only included if the dependency is enabled by
+/// something else. A `feature_selection` of `None` resolves `--all-features`.
+pub fn resolve_features(
+    graph: &BTreeMap<String, Vec<String>>,
+    feature_selection: Option<&[String]>,
+) -> BTreeSet<String> {
+    let mut stack: Vec<String> = match feature_selection {
+        None => graph.keys().cloned().collect(),
+        Some(features) => features.to_vec(),
+    };
+    let mut enabled = BTreeSet::new();
+
+    while let Some(item) = stack.pop() {
+        if enabled.contains(&item) {
+            continue;
+        }
+        if let Some((dep, _)) = item.split_once('/') {
+            // Weak dependency features are resolved once everything else is enabled.
+            if !dep.ends_with('?') {
+                // Enables the dependency, plus its implicit feature if it has one.
+                stack.push(format!("dep:{}", dep));
+                if graph.contains_key(dep) {
+                    stack.push(dep.to_owned());
+                }
+            }
+        } else if let Some(enables) = graph.get(&item) {
+            stack.extend(enables.iter().cloned());
+        }
+        enabled.insert(item);
+    }
+
+    let weak: Vec<String> = enabled.iter().filter(|item| item.contains("?/")).cloned().collect();
+    for item in weak {
+        enabled.remove(&item);
+        if let Some((dep, feature)) = item.split_once("?/") {
+            if enabled.contains(&format!("dep:{}", dep)) {
+                enabled.insert(format!("{}/{}", dep, feature));
+            }
+        }
+    }
+
+    enabled
 }
 
-/// Test auto-discovered features with configurable sampling strategy.
+/// Plan auto-discovered feature sets with configurable sampling strategy, seeded by `commit`.
 fn discovered_feature_matrix(
-    sh: &Shell,
-    toolchain: Toolchain,
     features: &[String],
-    strategy: SampleStrategy,
-    cargo_args: &[String],
+    config: &TestConfig,
+    commit: Option<String>,
+) -> Vec<FeatureSet> {
+    // Test each feature individually.
+    let mut sets: Vec<FeatureSet> = features
+        .iter()
+        .map(|feature| FeatureSet::new(FeatureSetKind::Individual, vec![feature.clone()]))
+        .collect();
+
+    // Generate feature subsets according to strategy.
+    sets.extend(
+        config
+            .sample_strategy
+            .generate_subsets(features, commit, config.pairwise_strength.unwrap_or(2))
+            .into_iter()
+            .map(|subset| FeatureSet::new(FeatureSetKind::Subset, subset)),
+    );
+
+    sets
+}
+
+/// Build and test each feature set, on up to `jobs` concurrent workers.
+///
+/// Sets which passed in a previous run are skipped when resuming, and every other set is marked
+/// done as soon as it passes. Each worker builds into its own target directory under
+/// `<target-dir>/rbmt/` so that cargo's build directory lock does not serialize them. Workers
+/// stop picking up new sets once any set fails, and the first failure in plan order is returned.
+/// The outcome of every set which was started is added to the summary in plan order.
+fn run_feature_sets(
+    sh: &Shell,
+    ctx: &CommitContext,
+    sets: &[FeatureSet],
     msrv_overrides: &MsrvOverrides,
     summary: &mut PackageSummary,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Test each feature individually.
-    for feature in features {
-        rbmt_eprintln!("Testing individual feature in {}: {}", summary.name, feature);
-        test_features(
+    let package_name = summary.name.as_str();
+    let options = ctx.options;
+
+    let mut outcomes: Vec<Option<UnitOutcome>> = sets
+        .iter()
+        .map(|set| {
+            ctx.is_done(package_name, &set.journal_unit())
+                .then(|| UnitOutcome::resumed(UnitKind::FeatureSet, set.to_string()))
+        })
+        .collect();
+    let pending: Vec<usize> = (0..sets.len()).filter(|&i| outcomes[i].is_none()).collect();
+    if pending.len() < sets.len() {
+        rbmt_eprintln!(
+            "Skipping {} feature set(s) in {}, passed previously",
+            sets.len() - pending.len(),
+            package_name
+        );
+    }
+
+    // Tests which only passed on a retry, from every worker.
+    let flaky = Mutex::new(Vec::new());
+    // Build and test a single set, returning its outcome. Unless `prepared`, the toolchain of the
+    // set, which depends on its MSRV override, is installed first.
+    let test_set = |sh: &Shell, set: &FeatureSet, prepared: bool| {
+        // Worker shells do not inherit the environment set on the main shell.
+        for (key, value) in ctx.sanitizer_env {
+            sh.set_var(key, value);
+        }
+        let start = Instant::now();
+        let test = || {
+            if !prepared {
+                let msrv_override = msrv_overrides.get(set.features.as_deref())?;
+                prepare_toolchain_with_override(sh, ctx.toolchain, msrv_override)?;
+            }
+            let tests =
+                test_features(sh, set.features.as_deref(), &options.cargo_args, options.retries)?;
+            if !tests.is_empty() {
+                rbmt_eprintln!(
+                    "Flaky test(s) in {} with {}: {}",
+                    package_name,
+                    set,
+                    tests.join(", ")
+                );
+                if let Ok(mut flaky) = flaky.lock() {
+                    flaky.extend(tests.iter().map(|test| format!("{} ({})", test, set)));
+                }
+                if options.strict_flaky {
+                    return Err(
+                        format!("Test(s) only passed on a retry: {}", tests.join(", ")).into()
+                    );
+                }
+            }
+            ctx.mark_done(package_name, &set.journal_unit())
+        };
+        let result = test();
+        UnitOutcome::finished(UnitKind::FeatureSet, set.to_string(), start, &result)
+    };
+
+    // Plans are recorded in order, with the toolchain of each set.
+    let workers = if plan::is_active() { 1 } else { options.jobs.get().min(pending.len()) };
+    let mut start_failed = false;
+    if workers <= 1 {
+        for &index in &pending {
+            rbmt_eprintln!("Testing {} in {}", sets[index], package_name);
+            let outcome = test_set(sh, &sets[index], false);
+            let failed = matches!(outcome.status, UnitStatus::Failed(_));
+            outcomes[index] = Some(outcome);
+            if failed {
+                break;
+            }
+        }
+    } else {
+        // Workers are handed the toolchain resolved for their set, so they are not racing each
+        // other through rustup.
+        let rustup_toolchains =
+            prepare_set_toolchains(sh, ctx.toolchain, sets, &pending, msrv_overrides)?;
+        let test_prepared_set = |worker_sh: &Shell, index: usize| {
+            if let Some(rustup_toolchain) = &rustup_toolchains[index] {
+                worker_sh.set_var(RUSTUP_TOOLCHAIN, rustup_toolchain);
+            }
+            test_set(worker_sh, &sets[index], true)
+        };
+
+        start_failed = test_sets_concurrently(
             sh,
-            toolchain,
-            Some(std::slice::from_ref(feature)),
-            cargo_args,
-            msrv_overrides,
+            package_name,
+            sets,
+            &pending,
+            workers,
+            &test_prepared_set,
+            &mut outcomes,
         )?;
-        summary.individual_features.push(feature.clone());
     }
 
-    // Generate and test feature subsets according to strategy.
-    let commit = git::current_commit_id(sh);
-    for subset in strategy.generate_subsets(features, commit) {
-        rbmt_eprintln!("Testing feature set in {}: {:?}", summary.name, subset);
-        test_features(sh, toolchain, Some(&subset), cargo_args, msrv_overrides)?;
-        summary.feature_subsets.push(subset);
+    let mut flaky = flaky.into_inner().unwrap_or_else(std::sync::PoisonError::into_inner);
+    flaky.sort();
+    summary.flaky_tests.extend(flaky);
+
+    record_outcomes(sets, outcomes, summary)?;
+    if start_failed {
+        return Err(format!("Feature matrix workers for {} failed to start", summary.name).into());
     }
 
     Ok(())
 }
 
+/// Add the outcomes of the feature sets to the summary, failing with the first failed set.
+fn record_outcomes(
+    sets: &[FeatureSet],
+    outcomes: Vec<Option<UnitOutcome>>,
+    summary: &mut PackageSummary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut first_failure = None;
+    for (set, outcome) in sets.iter().zip(outcomes) {
+        let Some(outcome) = outcome else { continue };
+        if let (None, UnitStatus::Failed(failure)) = (&first_failure, &outcome.status) {
+            first_failure =
+                Some(format!("Testing {} in {} failed: {}", set, summary.name, failure.message));
+        }
+        summary.units.push(outcome);
+    }
+    match first_failure {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Install the toolchain of every `pending` set before the workers start.
+///
+/// Returns the `RUSTUP_TOOLCHAIN` each set is tested with, by index, `None` where the active
+/// toolchain is used because `rustup` is not available.
+fn prepare_set_toolchains(
+    sh: &Shell,
+    toolchain: Toolchain,
+    sets: &[FeatureSet],
+    pending: &[usize],
+    msrv_overrides: &MsrvOverrides,
+) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
+    let mut rustup_toolchains = vec![None; sets.len()];
+    for &index in pending {
+        let msrv_override = msrv_overrides.get(sets[index].features.as_deref())?;
+        prepare_toolchain_with_override(sh, toolchain, msrv_override)?;
+        rustup_toolchains[index] = sh.var(RUSTUP_TOOLCHAIN).ok();
+    }
+    Ok(rustup_toolchains)
+}
+
+/// Build and test the `pending` sets on `workers` concurrent workers, filling in `outcomes`.
+///
+/// Returns `true` if a worker failed to start.
+fn test_sets_concurrently<F>(
+    sh: &Shell,
+    package_name: &str,
+    sets: &[FeatureSet],
+    pending: &[usize],
+    workers: usize,
+    test_set: &F,
+    outcomes: &mut [Option<UnitOutcome>],
+) -> Result<bool, Box<dyn std::error::Error>>
+where
+    F: Fn(&Shell, usize) -> UnitOutcome + Sync,
+{
+    rbmt_eprintln!(
+        "Testing {} feature set(s) in {} on {} workers",
+        pending.len(),
+        package_name,
+        workers
+    );
+    let package_dir = sh.current_dir();
+    let jobs_dir = get_target_dir(sh)?.join("rbmt");
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new(outcomes);
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            let (package_dir, jobs_dir) = (&package_dir, &jobs_dir);
+            let (next, failed, results) = (&next, &failed, &results);
+            scope.spawn(move || {
+                let worker_sh = match Shell::new() {
+                    Ok(worker_sh) => worker_sh,
+                    Err(e) => {
+                        failed.store(true, Ordering::Relaxed);
+                        rbmt_eprintln!("Failed to start worker {}: {}", worker, e);
+                        return;
+                    }
+                };
+                worker_sh.change_dir(package_dir);
+                worker_sh.set_var("CARGO_TARGET_DIR", jobs_dir.join(format!("job-{}", worker)));
+
+                while !failed.load(Ordering::Relaxed) {
+                    let Some(&index) = pending.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+
+                    rbmt_eprintln!(
+                        "Testing {} in {} (worker {})",
+                        sets[index],
+                        package_name,
+                        worker
+                    );
+                    let outcome = test_set(&worker_sh, index);
+                    if matches!(outcome.status, UnitStatus::Failed(_)) {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    if let Ok(mut results) = results.lock() {
+                        results[index] = Some(outcome);
+                    }
+                }
+            });
+        }
+    });
+
+    results.into_inner().map_err(|_| "Feature matrix worker panicked")?;
+    Ok(failed.load(Ordering::Relaxed))
+}
+
+/// Target built for no-std packages when no targets are configured.
+const NO_STD_TARGET: &str = "thumbv7m-none-eabi";
+
 /// Detect if a package is attempting to be no-std.
 fn is_no_std_package(sh: &Shell, package_dir: &Path) -> Result<bool, Box<dyn std::error::Error>> {
     // Use cargo metadata to find the library target's source path.
@@ -613,30 +2070,500 @@ fn is_no_std_package(sh: &Shell, package_dir: &Path) -> Result<bool, Box<dyn std
     let lib_path =
         PathBuf::from(lib_target["src_path"].as_str().ok_or("Missing src_path in lib target")?);
 
-    // Check for #![no_std] attribute.
     let contents = std::fs::read_to_string(lib_path)?;
-    Ok(contents.lines().any(|line| line.trim() == "#![no_std]"))
+    Ok(declares_no_std(&contents))
+}
+
+/// Returns `true` if the crate source declares `#![no_std]`, either unconditionally or through
+/// `#![cfg_attr(<predicate>, no_std)]`.
+///
+/// Inner attributes are matched with all whitespace removed, so attributes which are spread
+/// over several lines are found too. Comments are ignored.
+fn declares_no_std(source: &str) -> bool {
+    let code: String = strip_comments(source).chars().filter(|c| !c.is_whitespace()).collect();
+
+    let mut rest = code.as_str();
+    while let Some(start) = rest.find("#![") {
+        rest = &rest[start + 3..];
+
+        // Find the closing bracket of the attribute.
+        let mut depth = 1;
+        let Some(end) = rest.find(|c| {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        }) else {
+            return false;
+        };
+        let attr = &rest[..end];
+        rest = &rest[end..];
+
+        if attr == "no_std" {
+            return true;
+        }
+        // The attributes of `cfg_attr` follow its top-level predicate.
+        if let Some(args) = attr.strip_prefix("cfg_attr(").and_then(|a| a.strip_suffix(')')) {
+            let mut depth = 0;
+            let attrs = args.split(|c| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                c == ',' && depth == 0
+            });
+            if attrs.skip(1).any(|a| a == "no_std") {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Remove line and (possibly nested) block comments from Rust source, keeping string literals.
+fn strip_comments(source: &str) -> String {
+    let mut code = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            code.push(c);
+            match c {
+                '\\' => code.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut depth = 1;
+                while depth > 0 {
+                    match (chars.next(), chars.peek()) {
+                        (Some('/'), Some('*')) => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        (Some('*'), Some('/')) => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        (None, _) => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {
+                in_string = c == '"';
+                code.push(c);
+            }
+        }
+    }
+    code
 }
 
-/// Check no-std compatibility if the package declares `#![no_std]`.
-fn do_no_std_check(
+/// Build for each configured cross-compilation target, then for each `no_std_features` set.
+///
+/// Unless a configured target already builds for [`NO_STD_TARGET`], checks no-std compatibility
+/// if the package declares `#![no_std]` by building for it with no default features. The no-std
+/// builds
+/// catch `std` being pulled in through a feature (e.g. a dependency's `std` feature) since the
+/// target has no `std` to link.
+fn do_target_builds(
     sh: &Shell,
+    ctx: &CommitContext,
     package_dir: &Path,
+    config: &TestConfig,
     summary: &mut PackageSummary,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    const NO_STD_TARGET: &str = "thumbv7m-none-eabi";
-    if !is_no_std_package(sh, package_dir)? {
-        rbmt_eprintln!("{} does not appear to be no-std, skipping test", summary.name);
-        return Ok(());
+    for target in &plan_targets(sh, package_dir, config, &summary.name)? {
+        let unit = target.journal_unit();
+        if !ctx.in_shard(&summary.name, &unit) {
+            continue;
+        }
+        if ctx.is_done(&summary.name, &unit) {
+            rbmt_eprintln!("Skipping build for {} of {}, passed previously", target, summary.name);
+            summary.units.push(UnitOutcome::resumed(UnitKind::Target, target.to_string()));
+            summary.targets.push(target.to_string());
+            continue;
+        }
+
+        rbmt_eprintln!("Building {} for target {}", summary.name, target);
+        let start = Instant::now();
+        let result = build_target(sh, ctx.toolchain, target, &config.msrv_overrides)
+            .and_then(|()| ctx.mark_done(&summary.name, &unit));
+        summary.units.push(UnitOutcome::finished(
+            UnitKind::Target,
+            target.to_string(),
+            start,
+            &result,
+        ));
+        result?;
+        summary.targets.push(target.to_string());
     }
 
-    rbmt_eprintln!(
-        "Detected {} as a no-std package, building for target: {}",
-        summary.name,
-        NO_STD_TARGET
-    );
-    rbmt_cmd!(sh, "cargo build --target {NO_STD_TARGET} --no-default-features")
-        .run_with_capture()?;
-    summary.no_std_checked = true;
     Ok(())
 }
+
+/// Plan the cross-compilation builds of the package, see [`do_target_builds`].
+fn plan_targets(
+    sh: &Shell,
+    package_dir: &Path,
+    config: &TestConfig,
+    package_name: &str,
+) -> Result<Vec<TargetConfig>, Box<dyn std::error::Error>> {
+    let covered = config.targets.iter().any(|target| target.triple == NO_STD_TARGET);
+    let no_std = !covered && is_no_std_package(sh, package_dir)?;
+    if no_std {
+        rbmt_eprintln!(
+            "Detected {} as a no-std package, building for target: {}",
+            package_name,
+            NO_STD_TARGET
+        );
+    } else if config.targets.is_empty() && config.no_std_features.is_empty() {
+        rbmt_eprintln!("{} does not appear to be no-std, skipping test", package_name);
+    }
+    Ok(target_builds(config, no_std))
+}
+
+/// The configured targets, the build for [`NO_STD_TARGET`] of a `no_std` package unless a
+/// configured target already builds for it, and the `no_std_features` builds.
+fn target_builds(config: &TestConfig, no_std: bool) -> Vec<TargetConfig> {
+    let mut targets = config.targets.clone();
+    if no_std && !targets.iter().any(|target| target.triple == NO_STD_TARGET) {
+        targets.push(TargetConfig::no_std(Vec::new()));
+    }
+    targets.extend(config.no_std_features.iter().map(|f| TargetConfig::no_std(f.clone())));
+    targets
+}
+
+/// Build the package for a cross-compilation target.
+fn build_target(
+    sh: &Shell,
+    toolchain: Toolchain,
+    target: &TargetConfig,
+    msrv_overrides: &MsrvOverrides,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let msrv_override = msrv_overrides.get(Some(&target.features))?;
+    prepare_toolchain_with_override(sh, toolchain, msrv_override)?;
+    add_target(sh, &target.triple)?;
+
+    let mut cmd = cargo_cmd(sh).arg("build").arg("--target").arg(&target.triple);
+    if target.no_default_features {
+        cmd = cmd.arg("--no-default-features");
+    }
+    if !target.features.is_empty() {
+        // Avoid issues with feature names which contain a hyphen.
+        cmd = cmd.arg("--features").arg(target.features.join(","));
+    }
+    cmd.run_with_capture()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(n: usize) -> Vec<String> { (0..n).map(|i| format!("f{}", i)).collect() }
+
+    /// Check every `strength` combination of features is covered in every on/off state by the
+    /// subsets plus the empty, individual, and full sets tested elsewhere.
+    fn assert_covers(features: &[String], subsets: &[Vec<String>], strength: usize) {
+        let mut rows: Vec<Vec<bool>> =
+            vec![vec![false; features.len()], vec![true; features.len()]];
+        for i in 0..features.len() {
+            rows.push((0..features.len()).map(|j| i == j).collect());
+        }
+        rows.extend(subsets.iter().map(|s| features.iter().map(|f| s.contains(f)).collect()));
+
+        let indices: Vec<usize> = (0..features.len()).collect();
+        for tuple in combinations(&indices, strength) {
+            for state in 0..1u32 << strength {
+                assert!(
+                    rows.iter().any(|row| tuple_state(&tuple, |i| row[i]) == state),
+                    "{:?} in state {:b} not covered",
+                    tuple,
+                    state
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn covering_subsets_cover_all_combinations() {
+        for n in 2..=12 {
+            let features = features(n);
+            for strength in [2, 3] {
+                let subsets = generate_covering_subsets(&features, strength);
+                assert_covers(&features, &subsets, strength.min(n));
+            }
+        }
+    }
+
+    #[test]
+    fn covering_subsets_are_small_and_deterministic() {
+        let features = features(16);
+        let pairwise = generate_covering_subsets(&features, 2);
+        let threewise = generate_covering_subsets(&features, 3);
+        assert_eq!(pairwise, generate_covering_subsets(&features, 2));
+        assert_eq!(threewise, generate_covering_subsets(&features, 3));
+        assert!(pairwise.len() <= 16, "{} pairwise subsets", pairwise.len());
+        assert!(threewise.len() <= 40, "{} three-wise subsets", threewise.len());
+        // Empty, individual, and full sets are tested elsewhere.
+        assert!(threewise.iter().all(|s| s.len() > 1 && s.len() < features.len()));
+    }
+
+    fn graph(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(feature, enables)| {
+                (feature.to_string(), enables.iter().map(|&e| e.to_owned()).collect())
+            })
+            .collect()
+    }
+
+    fn resolve(graph: &BTreeMap<String, Vec<String>>, features: &[&str]) -> Vec<String> {
+        let features: Vec<String> = features.iter().map(|&f| f.to_owned()).collect();
+        resolve_features(graph, Some(&features)).into_iter().collect()
+    }
+
+    #[test]
+    fn resolve_features_follows_graph() {
+        let graph = graph(&[
+            ("default", &["std"]),
+            ("std", &["alloc", "serde?/std"]),
+            ("alloc", &[]),
+            ("serde", &["dep:serde"]),
+            ("rand", &["dep:rand", "rand/std"]),
+        ]);
+
+        assert_eq!(resolve(&graph, &[]), Vec::<String>::new());
+        assert_eq!(resolve(&graph, &["std"]), ["alloc", "std"]);
+        assert_eq!(resolve(&graph, &["std", "alloc"]), resolve(&graph, &["std"]));
+        // Weak dependency features only apply once the dependency is enabled.
+        assert_eq!(
+            resolve(&graph, &["std", "serde"]),
+            ["alloc", "dep:serde", "serde", "serde/std", "std"]
+        );
+        assert_eq!(resolve(&graph, &["rand"]), ["dep:rand", "rand", "rand/std"]);
+        // A dependency feature enables the dependency's implicit feature.
+        assert_eq!(resolve(&graph, &["serde/derive"]), ["dep:serde", "serde", "serde/derive"]);
+        assert!(resolve_features(&graph, None).contains("default"));
+    }
+
+    #[test]
+    fn skip_equivalent_feature_sets() {
+        let graph = graph(&[("std", &["alloc"]), ("alloc", &[]), ("foo", &[])]);
+        let sets = vec![
+            FeatureSet { kind: FeatureSetKind::All, features: None },
+            FeatureSet::new(FeatureSetKind::None, Vec::new()),
+            FeatureSet::new(FeatureSetKind::Individual, vec!["std".to_owned()]),
+            FeatureSet::new(FeatureSetKind::Subset, vec!["alloc".to_owned(), "std".to_owned()]),
+            FeatureSet::new(
+                FeatureSetKind::Exact,
+                vec!["alloc".to_owned(), "foo".to_owned(), "std".to_owned()],
+            ),
+        ];
+        let mut summary = PackageSummary::default();
+
+        let kept = skip_equivalent_sets(&graph, sets, &mut summary);
+
+        assert_eq!(kept.len(), 3);
+        assert_eq!(summary.equivalent_sets.len(), 2);
+        assert_eq!(summary.equivalent_sets[0].0.to_string(), r#"feature set ["alloc", "std"]"#);
+        assert_eq!(summary.equivalent_sets[0].1.to_string(), "individual feature std");
+        assert_eq!(summary.equivalent_sets[1].1.to_string(), "all features");
+    }
+
+    #[test]
+    fn detect_no_std() {
+        assert!(declares_no_std("#![no_std]\n"));
+        assert!(declares_no_std("//! Docs.\n\n#![ no_std ]\n"));
+        assert!(declares_no_std("#![cfg_attr(not(feature = \"std\"), no_std)]\n"));
+        assert!(declares_no_std(
+            "#![cfg_attr(\n    all(not(test), not(feature = \"std\")),\n    no_std\n)]\n"
+        ));
+        assert!(declares_no_std(
+            "#![doc(html_root_url = \"https://docs.rs/x\")]\n#![cfg_attr(docsrs, feature(doc_cfg))]\n#![no_std]\n"
+        ));
+
+        assert!(!declares_no_std("fn main() {}\n"));
+        assert!(!declares_no_std("// #![no_std]\n"));
+        assert!(!declares_no_std("#![cfg_attr(no_std, doc = \"x\")]\n"));
+        assert!(!declares_no_std("#![cfg_attr(feature = \"no_std\", doc = \"x\")]\n"));
+
+        // Block and trailing comments are ignored, but not comment markers inside strings.
+        assert!(declares_no_std("#![no_std] // Only core and alloc.\n"));
+        assert!(declares_no_std("#![cfg_attr(/* not(test) */ not(feature = \"std\"), no_std)]\n"));
+        assert!(declares_no_std("#![doc = \"// /* x\"]\n#![no_std]\n"));
+        assert!(!declares_no_std("/*\n#![no_std]\n*/\nfn main() {}\n"));
+        assert!(!declares_no_std("/* outer /* #![no_std] */ #![no_std] */\n"));
+        assert!(!declares_no_std("fn main() {} // #![no_std]\n"));
+    }
+
+    #[test]
+    fn no_std_target_builds() {
+        let triples = |config: &str, no_std| {
+            let config = TestConfig::parse(config).unwrap();
+            target_builds(&config, no_std).iter().map(ToString::to_string).collect::<Vec<_>>()
+        };
+
+        // The automatic no-std build is kept alongside other configured targets.
+        let wasm =
+            "[package.metadata.rbmt.test]\ntargets = [{ triple = \"wasm32-unknown-unknown\" }]\n";
+        assert_eq!(triples(wasm, true), ["wasm32-unknown-unknown", "thumbv7m-none-eabi"]);
+        assert_eq!(triples(wasm, false), ["wasm32-unknown-unknown"]);
+        assert_eq!(triples("", true), ["thumbv7m-none-eabi"]);
+
+        // Unless a configured target already builds for it.
+        let thumb = "[package.metadata.rbmt.test]\ntargets = [{ triple = \"thumbv7m-none-eabi\", features = [\"alloc\"] }]\nno_std_features = [[\"serde\"]]\n";
+        assert_eq!(
+            triples(thumb, true),
+            ["thumbv7m-none-eabi [alloc]", "thumbv7m-none-eabi [serde]"]
+        );
+    }
+
+    #[test]
+    fn covering_subsets_too_few_features() {
+        assert!(generate_covering_subsets(&features(0), 2).is_empty());
+        assert!(generate_covering_subsets(&features(1), 3).is_empty());
+    }
+
+    #[test]
+    fn parse_failed_tests() {
+        let stdout = "\nrunning 2 tests\ntest tests::bad ... FAILED\ntest tests::ok ... ok\n\n\
+            failures:\n\n---- tests::bad stdout ----\n    indented output\n\n\
+            thread 'tests::bad' panicked at src/lib.rs:8:50:\nx\n\n\
+            failures:\n    tests::bad\n\n\
+            test result: FAILED. 1 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out\n\n\
+            running 1 test\ntest src/lib.rs - f (line 1) ... FAILED\n\n\
+            failures:\n    src/lib.rs - f (line 1)\n\n\
+            test result: FAILED. 0 passed; 1 failed; 0 ignored; 0 measured; 0 filtered out\n";
+        let stderr = "     Running unittests src/lib.rs (target/debug/deps/flaky-99c6)\n\
+            error: test failed, to rerun pass `--lib`\n\
+            error: doctest failed, to rerun pass `--doc`\n\
+            error: 2 targets failed:\n    `--lib`\n    `--doc`\n";
+
+        let failed = FailedTests::parse(stdout, stderr).expect("failed tests");
+        assert_eq!(failed.targets, ["--lib", "--doc"]);
+        assert_eq!(failed.tests, ["tests::bad", "src/lib.rs - f (line 1)"]);
+
+        // A crashed target has no result line, so the failed tests are unknown.
+        let failed = FailedTests::parse(
+            stdout,
+            "error: test failed, to rerun pass `--lib`\n\
+            error: test failed, to rerun pass `--test t`\n\
+            error: doctest failed, to rerun pass `--doc`\n",
+        )
+        .expect("failed tests");
+        assert!(failed.tests.is_empty());
+        assert_eq!(failed.names(), ["--lib", "--test t", "--doc"]);
+
+        // A build failure has no failed targets.
+        assert_eq!(FailedTests::parse("", "error[E0425]: cannot find value `x`\n"), None);
+    }
+
+    #[test]
+    fn parse_example_config() {
+        #[derive(Deserialize)]
+        struct Examples {
+            examples: Vec<ExampleConfig>,
+        }
+
+        let config: Examples = toml::from_str(
+            r#"examples = [
+                "bip32",
+                "bip32:serde rand",
+                { name = "bip32", args = ["--network", "testnet"], stdout = "examples/bip32.stdout" },
+            ]"#,
+        )
+        .unwrap();
+        let labels: Vec<String> = config.examples.iter().map(ToString::to_string).collect();
+        assert_eq!(labels, ["bip32", "bip32:serde rand", "bip32 -- --network testnet"]);
+        assert_eq!(config.examples[1].features, ["serde", "rand"]);
+        assert_eq!(config.examples[2].stdout, Some(PathBuf::from("examples/bip32.stdout")));
+
+        assert!(toml::from_str::<Examples>(r#"examples = ["bip32:serde:rand"]"#).is_err());
+    }
+
+    #[test]
+    fn line_diff_marks_changed_lines() {
+        let diff = line_diff("xpub\naddr1\naddr2\n", "xpub\naddr1\naddr3\naddr4\n");
+        assert_eq!(diff, "   3 - addr2\n   3 + addr3\n   4 + addr4\n");
+        assert_eq!(line_diff("a\n", "a"), "(differs in trailing newlines)\n");
+    }
+
+    #[test]
+    fn inferred_msrv_overrides() {
+        let map = msrv::overrides;
+        let features = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect::<Vec<_>>();
+        let overrides = MsrvOverrides {
+            manual: map(&[("serde", "1.70.0")]).into_iter().collect(),
+            inferred: map(&[("serde", "1.80.0"), ("rand", "1.63.0"), ("encoding", "1.74.0")]),
+        };
+
+        // A manual override wins over the inferred one of the same feature.
+        assert_eq!(overrides.get(Some(&features(&["serde"]))).unwrap(), Some("1.70.0"));
+        assert_eq!(overrides.get(Some(&features(&["rand", "std"]))).unwrap(), Some("1.63.0"));
+        // The highest override of the set is needed.
+        assert_eq!(overrides.get(Some(&features(&["serde", "encoding"]))).unwrap(), Some("1.74.0"));
+        assert_eq!(overrides.get(None).unwrap(), Some("1.74.0"));
+        assert_eq!(overrides.get(Some(&features(&["std"]))).unwrap(), None);
+
+        // Inference is opt-in.
+        assert!(!TestConfig::parse("[package]\nname = \"io\"\n").unwrap().infer_msrv_overrides);
+        let config = TestConfig::parse(
+            "[package.metadata.rbmt.test]\ninfer_msrv_overrides = true\nmsrv_overrides = { serde = \"1.70.0\" }\n",
+        )
+        .unwrap();
+        assert!(config.infer_msrv_overrides);
+        assert_eq!(config.msrv_overrides(), map(&[("serde", "1.70.0")]));
+    }
+
+    #[test]
+    fn sanitizer_env_and_opt_in() {
+        let host = "x86_64-unknown-linux-gnu";
+        let existing = |var: &str| (var == "RUSTFLAGS").then(|| "-C debuginfo=1".to_owned());
+
+        assert_eq!(
+            compose_sanitizer_env(Sanitizer::Address, host.to_owned(), existing),
+            [
+                ("RUSTFLAGS", "-C debuginfo=1 -Zsanitizer=address".to_owned()),
+                ("RUSTDOCFLAGS", "-Zsanitizer=address".to_owned()),
+                ("CARGO_BUILD_TARGET", host.to_owned()),
+            ]
+        );
+        assert_eq!(
+            compose_sanitizer_env(Sanitizer::Memory, host.to_owned(), |_| None),
+            [
+                ("RUSTFLAGS", "-Zsanitizer=memory".to_owned()),
+                ("RUSTDOCFLAGS", "-Zsanitizer=memory".to_owned()),
+                ("CARGO_BUILD_TARGET", host.to_owned()),
+                ("CARGO_UNSTABLE_BUILD_STD", "std".to_owned()),
+            ]
+        );
+
+        let config = SanitizersConfig::parse(
+            r#"
+            [package]
+            name = "hashes"
+
+            [package.metadata.rbmt.sanitizers]
+            enabled = ["address", "thread"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.enabled, [Sanitizer::Address, Sanitizer::Thread]);
+        assert!(!config.enabled.contains(&Sanitizer::Memory));
+        assert!(SanitizersConfig::parse("[package]\nname = \"io\"\n").unwrap().enabled.is_empty());
+        assert!(SanitizersConfig::parse(
+            "[package.metadata.rbmt.sanitizers]\nenabled = [\"leak\"]\n"
+        )
+        .is_err());
+    }
+}
@@ -22,6 +22,7 @@ use toml_edit::DocumentMut;
 use xshell::Shell;
 
 use crate::environment::{get_workspace_root, CmdExt, WorkspaceManifest};
+use crate::plan;
 use crate::semantic_version::Version;
 
 /// Fixed components installed on every toolchain.
@@ -76,7 +77,7 @@ struct ToolchainsConfig {
 /// correct toolchain automatically. Setting this variable directly has the same
 /// effect and is the supported mechanism for propagating a toolchain choice into
 /// subprocesses without repeating the `+toolchain` flag on every inner call.
-const RUSTUP_TOOLCHAIN: &str = "RUSTUP_TOOLCHAIN";
+pub const RUSTUP_TOOLCHAIN: &str = "RUSTUP_TOOLCHAIN";
 
 /// The class of toolchain required for a task.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
@@ -243,17 +244,22 @@ fn rustup_version(sh: &Shell) -> Result<Version, Box<dyn std::error::Error>> {
     Err("Could not parse rustup version".into())
 }
 
-/// Install a single toolchain with the fixed components and target using `rustup`.
+/// Install a single toolchain with the fixed components and target using `rustup`, plus any
+/// additional `targets`.
 pub fn install_toolchain(
     sh: &Shell,
     toolchain: &str,
     force: bool,
+    targets: &[String],
 ) -> Result<(), Box<dyn std::error::Error>> {
     rbmt_eprintln!("Installing toolchain {}", toolchain);
 
+    let mut all_targets = vec![TARGET];
+    all_targets.extend(targets.iter().map(String::as_str).filter(|target| *target != TARGET));
+    let targets = all_targets.join(",");
     let mut install_cmd = rbmt_cmd!(
         sh,
-        "rustup toolchain install {toolchain} --component {COMPONENTS} --target {TARGET}"
+        "rustup toolchain install {toolchain} --component {COMPONENTS} --target {targets}"
     )
     // --no-self-update keeps rustup from updating itself, not related to toolchains.
     .arg("--no-self-update")
@@ -276,6 +282,50 @@ pub fn install_toolchain(
     Ok(())
 }
 
+/// Target triple of the host, as reported by the active `rustc`.
+pub fn host_target(sh: &Shell) -> Result<String, Box<dyn std::error::Error>> {
+    let version = rbmt_cmd!(sh, "rustc -vV").read()?;
+    version
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().to_owned())
+        .ok_or_else(|| "Could not determine the host target from rustc".into())
+}
+
+/// Add a component which is not part of the fixed set to the active toolchain.
+///
+/// Does nothing if `rustup` is not available, in which case the component is expected to
+/// already be installed.
+pub fn add_component(sh: &Shell, component: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if rbmt_cmd!(sh, "rustup --version").ignore_stderr().read().is_err() {
+        return Ok(());
+    }
+    rbmt_eprintln!("Adding component {}", component);
+    rbmt_cmd!(sh, "rustup component add {component}")
+        .env("RUSTUP_PERMIT_COPY_RENAME", "true")
+        .run_with_capture()?;
+    Ok(())
+}
+
+/// Add a target which is not part of the fixed set to the active toolchain.
+///
+/// The `toolchains` command already installs the configured targets, so this is a fallback for
+/// toolchains installed some other way. Does nothing if `rustup` is not available, in which case
+/// the target is expected to already be installed, or when planning.
+pub fn add_target(sh: &Shell, target: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if target == TARGET
+        || plan::is_active()
+        || rbmt_cmd!(sh, "rustup --version").ignore_stderr().read().is_err()
+    {
+        return Ok(());
+    }
+    rbmt_eprintln!("Adding target {}", target);
+    rbmt_cmd!(sh, "rustup target add {target}")
+        .env("RUSTUP_PERMIT_COPY_RENAME", "true")
+        .run_with_capture()?;
+    Ok(())
+}
+
 /// Ensures a [`Toolchain`] is ready for use (see [`prepare_toolchain_with_override`]).
 pub fn prepare_toolchain(
     sh: &Shell,
@@ -300,6 +350,25 @@ pub fn prepare_toolchain_with_override(
     required: Toolchain,
     msrv_override: Option<&str>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if plan::is_active() {
+        // Nothing is installed or verified, the plan only names the toolchain.
+        let version = match required {
+            Toolchain::Msrv => match msrv_override {
+                Some(version) => Some(version.to_owned()),
+                None if sh.current_dir().join("Cargo.toml").exists() =>
+                    Some(get_msrv_from_manifest(sh, &sh.current_dir().join("Cargo.toml"))?),
+                None => required.try_read_version(sh),
+            },
+            Toolchain::Nightly | Toolchain::Stable => required.try_read_version(sh),
+        };
+        plan::set_toolchain(version.as_deref().unwrap_or(match required {
+            Toolchain::Nightly => "nightly",
+            Toolchain::Stable => "stable",
+            Toolchain::Msrv => "msrv",
+        }));
+        return Ok(());
+    }
+
     // Install the toolchain if we have a version and rustup is available.
     // MSRV override only applies when MSRV is required.
     if let Some(version) = &msrv_override
@@ -308,7 +377,7 @@ pub fn prepare_toolchain_with_override(
         .or_else(|| required.try_read_version(sh))
     {
         if rbmt_cmd!(sh, "rustup --version").ignore_stderr().read().is_ok() {
-            install_toolchain(sh, version, false)?;
+            install_toolchain(sh, version, false, &[])?;
             sh.set_var(RUSTUP_TOOLCHAIN, version.clone());
         }
     }
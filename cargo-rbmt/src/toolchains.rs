@@ -1,8 +1,11 @@
 // SPDX-License-Identifier: MIT AND Apache-2.0
 
+use std::collections::BTreeSet;
+
 use xshell::Shell;
 
-use crate::environment::ProgressGuard;
+use crate::environment::{get_workspace_packages, ProgressGuard};
+use crate::test::TestConfig;
 use crate::toolchain::{install_toolchain, Toolchain};
 
 /// Status string for toolchains that are not configured.
@@ -10,6 +13,10 @@ const NOT_CONFIGURED: &str = "(not configured)";
 
 /// Install configured toolchains nightly, stable, and MSRV.
 ///
+/// Each toolchain also gets the cross-compilation targets configured in
+/// `[package.metadata.rbmt.test] targets` of any workspace package, since the tests build them
+/// with whichever toolchain they run on.
+///
 /// Optionally updates to latest `nightly` or `stable` version.
 ///
 /// When `msrv`, `nightly`, or `stable` is true, print the corresponding version
@@ -58,8 +65,10 @@ pub fn run(
         Toolchain::Stable.update_version(sh)?;
     }
 
+    let targets = configured_targets(sh)?;
+
     let nightly_status = if let Some(version) = Toolchain::Nightly.try_read_version(sh) {
-        install_toolchain(sh, &version, force)?;
+        install_toolchain(sh, &version, force, &targets)?;
         version
     } else {
         rbmt_eprintln!("No pinned nightly toolchain found in [workspace.metadata.rbmt.toolchains] or [package.metadata.rbmt.toolchains]");
@@ -67,7 +76,7 @@ pub fn run(
     };
 
     let stable_status = if let Some(version) = Toolchain::Stable.try_read_version(sh) {
-        install_toolchain(sh, &version, force)?;
+        install_toolchain(sh, &version, force, &targets)?;
         version
     } else {
         rbmt_eprintln!("No pinned stable toolchain found in [workspace.metadata.rbmt.toolchains] or [package.metadata.rbmt.toolchains]");
@@ -75,7 +84,7 @@ pub fn run(
     };
 
     let msrv_status = if let Some(version) = Toolchain::Msrv.try_read_version(sh) {
-        install_toolchain(sh, &version, force)?;
+        install_toolchain(sh, &version, force, &targets)?;
         version
     } else {
         rbmt_eprintln!("No MSRV (rust-version) found in any Cargo.toml in the workspace");
@@ -91,3 +100,12 @@ pub fn run(
 
     Ok(())
 }
+
+/// Target triples configured in `[package.metadata.rbmt.test] targets` across the workspace.
+fn configured_targets(sh: &Shell) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut targets = BTreeSet::new();
+    for package in get_workspace_packages(sh, &[])? {
+        targets.extend(TestConfig::load(&package.dir)?.target_triples());
+    }
+    Ok(targets.into_iter().collect())
+}
@@ -3,10 +3,11 @@
 //! Analyze the internal workspace dependency tree.
 
 use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
 
 use xshell::Shell;
 
-use crate::environment::{get_workspace_packages, Package, ProgressGuard};
+use crate::environment::{get_workspace_packages, get_workspace_root, Package, ProgressGuard};
 use crate::git;
 
 /// Analyze the internal workspace dependency tree.
@@ -28,6 +29,109 @@ pub fn run(
     print_release_waves(sh, &packages, baseline)
 }
 
+/// Names of the workspace packages with changes since `baseline`, plus every package which
+/// depends on them, directly or transitively.
+///
+/// Dev-dependencies count since a package's tests are affected by them. A changed file belongs to
+/// the innermost package directory containing it, and changes to the workspace's build
+/// configuration affect every package (see [`affects_workspace`]).
+pub fn changed_with_dependents(
+    sh: &Shell,
+    baseline: &str,
+) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let packages = get_workspace_packages(sh, &[])?;
+    let root = get_workspace_root(sh)?;
+    let files = git::changed_files(sh, baseline)?;
+
+    if files.iter().any(|file| affects_workspace(&root, file)) {
+        rbmt_eprintln!(
+            "Workspace manifest, lockfiles, or build configuration changed since {}",
+            baseline
+        );
+        return Ok(packages.into_iter().map(|p| p.name).collect());
+    }
+
+    let changed = files
+        .iter()
+        .filter_map(|file| {
+            packages
+                .iter()
+                .filter(|p| file.starts_with(&p.dir))
+                .max_by_key(|p| p.dir.components().count())
+        })
+        .map(|p| p.name.clone())
+        .collect();
+    Ok(with_dependents(&packages, changed))
+}
+
+/// Whether a changed file affects how every package builds: the root `Cargo.toml`, a
+/// `Cargo*.lock` lockfile, a `rust-toolchain` file at the root, or cargo configuration under
+/// the root `.cargo` directory, e.g. rustflags or target settings.
+fn affects_workspace(root: &Path, file: &Path) -> bool {
+    if file.starts_with(root.join(".cargo")) {
+        return true;
+    }
+    file.parent() == Some(root)
+        && file.file_name().and_then(|n| n.to_str()).is_some_and(|name| {
+            name == "Cargo.toml"
+                || name.starts_with("rust-toolchain")
+                || Path::new(name).extension().is_some_and(|ext| ext == "lock")
+                    && name.starts_with("Cargo")
+        })
+}
+
+/// Add every package which depends on one of the `changed` packages, directly or transitively.
+///
+/// A dev-dependency only affects the tests of the package declaring it, so the walk stops there
+/// and does not continue to that package's dependents.
+fn with_dependents(packages: &[Package], changed: BTreeSet<String>) -> BTreeSet<String> {
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut dev_dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for package in packages {
+        for dep in &package.deps {
+            dependents.entry(dep.as_str()).or_default().push(package.name.as_str());
+        }
+        for dep in &package.dev_deps {
+            dev_dependents.entry(dep.as_str()).or_default().push(package.name.as_str());
+        }
+    }
+
+    let mut stack: Vec<String> = changed.iter().cloned().collect();
+    let mut affected = changed;
+    let mut dev_affected = BTreeSet::new();
+    while let Some(name) = stack.pop() {
+        for dependent in dependents.get(name.as_str()).into_iter().flatten() {
+            if affected.insert((*dependent).to_owned()) {
+                stack.push((*dependent).to_owned());
+            }
+        }
+        for dependent in dev_dependents.get(name.as_str()).into_iter().flatten() {
+            dev_affected.insert((*dependent).to_owned());
+        }
+    }
+    affected.extend(dev_affected);
+    affected
+}
+
+/// The package and every workspace package it is built with: its dependencies and
+/// dev-dependencies, and their dependencies transitively.
+pub fn with_dependencies<'a>(packages: &'a [Package], name: &str) -> Vec<&'a Package> {
+    let by_name: HashMap<&str, &Package> = packages.iter().map(|p| (p.name.as_str(), p)).collect();
+    let mut found: BTreeSet<&str> = BTreeSet::new();
+    // Dev-dependencies only matter for the package itself, not for its dependencies.
+    let mut stack: Vec<&str> = match by_name.get(name) {
+        Some(package) => package.dev_deps.iter().map(String::as_str).chain([name]).collect(),
+        None => Vec::new(),
+    };
+    while let Some(name) = stack.pop() {
+        let Some(package) = by_name.get(name) else { continue };
+        if found.insert(name) {
+            stack.extend(package.deps.iter().map(String::as_str));
+        }
+    }
+    found.into_iter().map(|name| by_name[name]).collect()
+}
+
 /// Collect publishable workspace packages with only their internal dependencies.
 fn internal_packages(
     sh: &Shell,
@@ -139,3 +243,94 @@ fn package_depth<'a>(
     memo.insert(name, depth);
     depth
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn package(name: &str, deps: &[&str], dev_deps: &[&str]) -> Package {
+        Package {
+            name: name.to_owned(),
+            dir: PathBuf::from(name),
+            id: name.to_owned(),
+            deps: deps.iter().map(|d| (*d).to_owned()).collect(),
+            dev_deps: dev_deps.iter().map(|d| (*d).to_owned()).collect(),
+            features: Vec::new(),
+            feature_graph: std::collections::BTreeMap::new(),
+            publish: true,
+        }
+    }
+
+    #[test]
+    fn dependents_are_affected() {
+        // io <- primitives <- bitcoin, with units only a dev-dependency of primitives.
+        let packages = [
+            package("units", &["serde"], &[]),
+            package("io", &[], &[]),
+            package("primitives", &["io", "serde"], &["units"]),
+            package("bitcoin", &["primitives"], &[]),
+            package("fuzz", &["units"], &[]),
+        ];
+        let names = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(|n| (*n).to_owned()).collect()
+        };
+
+        assert_eq!(with_dependents(&packages, names(&["bitcoin"])), names(&["bitcoin"]));
+        assert_eq!(
+            with_dependents(&packages, names(&["io"])),
+            names(&["bitcoin", "io", "primitives"])
+        );
+        // Primitives only tests with units, so bitcoin is not affected through it.
+        assert_eq!(
+            with_dependents(&packages, names(&["units"])),
+            names(&["fuzz", "primitives", "units"])
+        );
+        assert_eq!(
+            with_dependents(&packages, names(&["units", "io"])),
+            names(&["bitcoin", "fuzz", "io", "primitives", "units"])
+        );
+        assert!(with_dependents(&packages, BTreeSet::new()).is_empty());
+    }
+
+    #[test]
+    fn workspace_files_affect_every_package() {
+        let root = Path::new("/ws");
+        for file in [
+            "Cargo.toml",
+            "Cargo.lock",
+            "Cargo-minimal.lock",
+            "rust-toolchain",
+            "rust-toolchain.toml",
+            ".cargo/config.toml",
+            ".cargo/config",
+        ] {
+            assert!(affects_workspace(root, &root.join(file)), "{}", file);
+        }
+        for file in ["README.md", "units/Cargo.toml", "units/rust-toolchain.toml", "deny.lock"] {
+            assert!(!affects_workspace(root, &root.join(file)), "{}", file);
+        }
+    }
+
+    #[test]
+    fn dependencies_are_found() {
+        let packages = [
+            package("units", &["serde"], &["io"]),
+            package("io", &[], &[]),
+            package("primitives", &["units"], &["hex"]),
+            package("hex", &[], &[]),
+            package("bitcoin", &["primitives"], &["fuzz"]),
+            package("fuzz", &["bitcoin"], &[]),
+        ];
+        let names = |name: &str| -> Vec<&str> {
+            with_dependencies(&packages, name).into_iter().map(|p| p.name.as_str()).collect()
+        };
+
+        assert_eq!(names("io"), ["io"]);
+        assert_eq!(names("units"), ["io", "units"]);
+        // The dev-dependencies of units and primitives are not needed to build them.
+        assert_eq!(names("bitcoin"), ["bitcoin", "fuzz", "primitives", "units"]);
+        assert!(names("missing").is_empty());
+    }
+}
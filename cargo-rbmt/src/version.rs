@@ -60,14 +60,12 @@ pub fn check(sh: &Shell) -> Result<(), Box<dyn std::error::Error>> {
                 )
                 .into());
             }
-        } else {
-            if expected != actual_version {
-                return Err(format!(
-                    "cargo-rbmt version mismatch: expected {}, found {}",
-                    expected, actual_version
-                )
-                .into());
-            }
+        } else if expected != actual_version {
+            return Err(format!(
+                "cargo-rbmt version mismatch: expected {}, found {}",
+                expected, actual_version
+            )
+            .into());
         }
     }
 
@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: MIT AND Apache-2.0
 
+use std::fmt::Write as _;
 use std::fs;
 use std::process::Command;
 
@@ -980,7 +981,7 @@ fn merge_pr(pr_number: u64, repo: Option<String>, branch: Option<String>) -> Res
     } else {
         message.push_str("\n\nACKs for top commit:\n");
         for (user, ack_msg) in &acks {
-            message.push_str(&format!("  {}:\n    {}\n", user, ack_msg));
+            let _ = writeln!(message, "  {}:\n    {}", user, ack_msg);
         }
         println!("\nFound {} ACK(s)", acks.len());
         for (user, msg) in &acks {
@@ -989,7 +990,7 @@ fn merge_pr(pr_number: u64, repo: Option<String>, branch: Option<String>) -> Res
     }
 
     // Add tree hash to message
-    message.push_str(&format!("\n\nTree-SHA512: {}", first_sha512));
+    let _ = write!(message, "\n\nTree-SHA512: {}", first_sha512);
 
     // Amend commit with full message
     Command::new("git")